@@ -2,7 +2,72 @@ defmodule Noodlex.Vcf do
   use Rustler, otp_app: :noodlex, crate: "noodlex", mode: :release
 
   def get_handle(_path), do: :erlang.nif_error(:nif_not_loaded)
+  def get_handle(_path, _opts), do: :erlang.nif_error(:nif_not_loaded)
+  def get_handle_mmap(_path), do: :erlang.nif_error(:nif_not_loaded)
+  def get_handle_from_binary(_data), do: :erlang.nif_error(:nif_not_loaded)
+  def get_handle_from_stdin(), do: :erlang.nif_error(:nif_not_loaded)
+  def handle_info(_handle), do: :erlang.nif_error(:nif_not_loaded)
+  def rewind(_handle), do: :erlang.nif_error(:nif_not_loaded)
+  def reload_header(_handle), do: :erlang.nif_error(:nif_not_loaded)
+  def set_parse_header(_handle, _header), do: :erlang.nif_error(:nif_not_loaded)
   def get_header(_handle), do: :erlang.nif_error(:nif_not_loaded)
+  def read_header_only(_path), do: :erlang.nif_error(:nif_not_loaded)
+  def valid_vcf(_path), do: :erlang.nif_error(:nif_not_loaded)
+  def valid_vcf?(path), do: valid_vcf(path)
+  def merge_headers(_handles), do: :erlang.nif_error(:nif_not_loaded)
+  def describe_info_key(_handle, _key), do: :erlang.nif_error(:nif_not_loaded)
+  def sv_info_definitions(_handle), do: :erlang.nif_error(:nif_not_loaded)
+  def info_keys(_handle), do: :erlang.nif_error(:nif_not_loaded)
+  def format_keys(_handle), do: :erlang.nif_error(:nif_not_loaded)
   def get_record(_handle), do: :erlang.nif_error(:nif_not_loaded)
+  def get_record(_handle, _opts), do: :erlang.nif_error(:nif_not_loaded)
+  def next_record(_handle), do: :erlang.nif_error(:nif_not_loaded)
+  def get_raw_record(_handle), do: :erlang.nif_error(:nif_not_loaded)
+  def read_raw_records(_handle, _count), do: :erlang.nif_error(:nif_not_loaded)
+  def get_sample_genotype(_handle, _sample_name), do: :erlang.nif_error(:nif_not_loaded)
+  def at_eof(_handle), do: :erlang.nif_error(:nif_not_loaded)
+  def at_eof?(handle), do: at_eof(handle)
+  def estimate_remaining(_handle), do: :erlang.nif_error(:nif_not_loaded)
+  def skip_records(_handle, _count), do: :erlang.nif_error(:nif_not_loaded)
   def get_records(_handle, _count), do: :erlang.nif_error(:nif_not_loaded)
+  def get_records(_handle, _count, _opts), do: :erlang.nif_error(:nif_not_loaded)
+  def get_records_with_cursor(_handle, _batch_size), do: :erlang.nif_error(:nif_not_loaded)
+  def get_records_range(_handle, _start_index, _end_index), do: :erlang.nif_error(:nif_not_loaded)
+  def get_records_min_qual(_handle, _min_qual, _count), do: :erlang.nif_error(:nif_not_loaded)
+
+  def get_records_min_qual(_handle, _min_qual, _count, _opts),
+    do: :erlang.nif_error(:nif_not_loaded)
+
+  def peek_first_record(_handle), do: :erlang.nif_error(:nif_not_loaded)
+  def get_records_for_contig(_handle, _contig, _count), do: :erlang.nif_error(:nif_not_loaded)
+
+  def get_records_in_range(_handle, _contig, _start, _end, _count),
+    do: :erlang.nif_error(:nif_not_loaded)
+
+  def get_records_lenient(_handle, _count), do: :erlang.nif_error(:nif_not_loaded)
+  def get_all_records(_handle), do: :erlang.nif_error(:nif_not_loaded)
+  def index(_path), do: :erlang.nif_error(:nif_not_loaded)
+  def query(_handle, _region), do: :erlang.nif_error(:nif_not_loaded)
+  def region_summary(_handle, _region), do: :erlang.nif_error(:nif_not_loaded)
+  def read_index(_path), do: :erlang.nif_error(:nif_not_loaded)
+  def count_records(_handle), do: :erlang.nif_error(:nif_not_loaded)
+  def tail(_handle, _n), do: :erlang.nif_error(:nif_not_loaded)
+  def check_sorted(_handle), do: :erlang.nif_error(:nif_not_loaded)
+  def sorted(_handle), do: :erlang.nif_error(:nif_not_loaded)
+  def sorted?(handle), do: sorted(handle)
+  def observed_contigs(_handle), do: :erlang.nif_error(:nif_not_loaded)
+  def records_per_contig(_handle), do: :erlang.nif_error(:nif_not_loaded)
+  def filter_histogram(_handle), do: :erlang.nif_error(:nif_not_loaded)
+  def genotype_concordance(_handle, _sample_a, _sample_b), do: :erlang.nif_error(:nif_not_loaded)
+  def phase_sets(_handle, _sample), do: :erlang.nif_error(:nif_not_loaded)
+  def get_writer(_path, _handle), do: :erlang.nif_error(:nif_not_loaded)
+  def get_writer(_path, _handle, _opts), do: :erlang.nif_error(:nif_not_loaded)
+  def open_writer_append(_path), do: :erlang.nif_error(:nif_not_loaded)
+  def write_record(_writer, _record), do: :erlang.nif_error(:nif_not_loaded)
+  def write_records(_writer, _records), do: :erlang.nif_error(:nif_not_loaded)
+  def close_writer(_writer), do: :erlang.nif_error(:nif_not_loaded)
+  def copy_records(_handle, _writer, _opts), do: :erlang.nif_error(:nif_not_loaded)
+  def export_json(_handle, _output_path, _opts), do: :erlang.nif_error(:nif_not_loaded)
+  def convert(_input_path, _output_path), do: :erlang.nif_error(:nif_not_loaded)
+  def normalize_record(_record), do: :erlang.nif_error(:nif_not_loaded)
 end
@@ -14,7 +14,20 @@ defmodule Noodlex.Vcf.Header do
 
     @type t :: %__MODULE__{
             id: atom(),
-            number: atom(),
+            number: non_neg_integer() | atom(),
+            type_: atom(),
+            description: String.t()
+          }
+  end
+
+  defmodule Format do
+    @enforce_keys [:id, :number, :type_, :description]
+
+    defstruct @enforce_keys
+
+    @type t :: %__MODULE__{
+            id: String.t(),
+            number: non_neg_integer() | atom(),
             type_: atom(),
             description: String.t()
           }
@@ -28,9 +41,29 @@ defmodule Noodlex.Vcf.Header do
     @type t :: %__MODULE__{id: String.t(), description: String.t()}
   end
 
-  @enforce_keys [:fileformat, :infos]
+  @enforce_keys [
+    :fileformat,
+    :infos,
+    :formats,
+    :filters,
+    :contigs,
+    :other,
+    :pedigrees,
+    :samples_meta,
+    :sample_names
+  ]
 
   defstruct @enforce_keys
 
-  @type t :: %__MODULE__{fileformat: FileFormat.t()}
+  @type t :: %__MODULE__{
+          fileformat: FileFormat.t(),
+          infos: %{atom() => Info.t()},
+          formats: %{String.t() => Format.t()},
+          filters: %{String.t() => Filter.t()},
+          contigs: %{String.t() => non_neg_integer() | nil},
+          other: %{String.t() => [String.t()]},
+          pedigrees: %{String.t() => %{String.t() => String.t()}},
+          samples_meta: %{String.t() => %{String.t() => String.t()}},
+          sample_names: [String.t()]
+        }
 end
@@ -0,0 +1,7 @@
+defmodule Noodlex.Vcf.Position do
+  @enforce_keys [:chrom, :start, :end]
+
+  defstruct @enforce_keys
+
+  @type t :: %__MODULE__{chrom: String.t(), start: non_neg_integer(), end: non_neg_integer()}
+end
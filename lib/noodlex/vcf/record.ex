@@ -1,14 +1,47 @@
 defmodule Noodlex.Vcf.Record do
+  defmodule Genotype do
+    @enforce_keys [:alleles, :phased]
+
+    defstruct @enforce_keys
+
+    @type t :: %__MODULE__{alleles: [non_neg_integer() | nil], phased: boolean()}
+  end
+
+  defmodule Breakend do
+    @enforce_keys [:mate_chrom, :mate_pos, :bracket, :join]
+
+    defstruct @enforce_keys
+
+    @type t :: %__MODULE__{
+            mate_chrom: String.t(),
+            mate_pos: non_neg_integer(),
+            bracket: String.t(),
+            join: :before | :after
+          }
+  end
+
   @enforce_keys [
+    :allele_info,
     :alternate_bases,
+    :alt_kinds,
+    :breakends,
     :chromosome,
+    :end_confidence_intervals,
     :filters,
     :format,
     :ids,
     :info,
+    :interval,
+    :line,
+    :passed,
+    :ploidy,
     :position,
+    :position_confidence_intervals,
     :quality_score,
     :reference_bases,
+    :reserved_info,
+    :resolved_alternate_bases,
+    :variant_type,
     :genotypes
   ]
 
@@ -16,16 +49,39 @@ defmodule Noodlex.Vcf.Record do
 
   @type filters :: :pass | {:fail, [String.t()]}
 
+  @typedoc "How a missing value is represented, chosen with the `missing:` option."
+  @type missing :: nil | :missing | String.t()
+
+  @type genotype_value ::
+          Genotype.t()
+          | integer()
+          | float()
+          | String.t()
+          | [integer() | float() | String.t() | missing()]
+          | missing()
+
   @type t :: %__MODULE__{
-          alternate_bases: String.t(),
+          allele_info: %{String.t() => %{String.t() => term()}},
+          alternate_bases: [String.t()],
+          alt_kinds: [:snv | :symbolic | :breakend | :other],
+          breakends: [Breakend.t() | nil],
           chromosome: String.t(),
+          end_confidence_intervals: {integer(), integer()} | nil,
           filters: atom(),
           format: [String.t()],
           ids: [String.t()],
-          info: %{String.t() => String.t()},
+          info: %{String.t() => String.t() | true} | [{String.t(), String.t() | true}],
+          interval: Noodlex.Vcf.Position.t(),
+          line: non_neg_integer(),
+          passed: boolean() | nil,
+          ploidy: %{String.t() => pos_integer() | nil},
           position: integer(),
-          quality_score: float(),
+          position_confidence_intervals: {integer(), integer()} | nil,
+          quality_score: float() | missing(),
           reference_bases: String.t(),
-          genotypes: %{String.t() => String.t()}
+          reserved_info: %{atom() => term()},
+          resolved_alternate_bases: [String.t() | %{id: String.t(), description: String.t()}],
+          variant_type: [:snv | :insertion | :deletion | :mnv | :other],
+          genotypes: %{String.t() => %{String.t() => genotype_value()} | nil}
         }
 end
@@ -1,13 +1,26 @@
-use itertools::Itertools;
+// rustler 0.26's derive and resource macros expand to code that newer toolchains lint on.
+#![allow(clippy::needless_borrows_for_generic_args, non_local_definitions)]
+
+use std::collections::{HashMap, VecDeque};
 use std::io::Error as IoError;
 use std::io::ErrorKind as IoErrorKind;
-use std::ops::Deref;
+use std::io::{BufRead, BufWriter, Cursor, Read, Seek, SeekFrom, Stdin, Write};
 use std::sync;
-use std::{fs::File, io::BufReader};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::{fs::File, fs::OpenOptions, io::BufReader};
 
+use flate2::read::MultiGzDecoder;
+use memmap2::Mmap;
+use noodles_bcf as bcf;
+use noodles_bgzf as bgzf;
+use noodles_csi::index::reference_sequence::bin::Chunk;
+use noodles_tabix as tabix;
 use noodles_vcf as vcf;
+use rayon::prelude::*;
+use rustler::Encoder;
 use rustler::Env;
 use rustler::Term;
+use rustler::TermType;
 use rustler::{Atom, Error as RustlerError, ResourceArc};
 
 mod atoms {
@@ -74,12 +87,386 @@ mod atoms {
         genotypes,
         pass,
         end_of_file,
+        not_seekable,
+        snv,
+        symbolic,
+        breakend,
+        compute_stats,
+        compute_allele_frequencies,
+        info,
+        format,
+        parse,
+        invalid_record,
+        unsupported_file_format,
+        buffer_size,
+        not_bgzf,
+        insertion,
+        deletion,
+        mnv,
+        pass_only,
+        header,
+        compressed,
+        before,
+        after,
+        missing,
+        unsorted,
+        conflicting_definition,
+        contig,
+        duplicate_sample,
+        closed,
+        ordered_info,
+        bgzf,
+        gzip,
+        none,
+        stdin,
+        vcf,
+        invalid_region,
+        on_error,
+        stop,
+        skip,
+        collect,
+        tbi,
+        csi,
+        binary,
+        sam,
+        generic,
+        max_field_len,
+        field_too_long,
+        valid,
+        select_samples,
+        keep_missing_qual,
+        compression,
+        bcf,
+        format_mismatch,
+        unsupported_format,
+        invalid_utf8,
+        info_id_too_long,
+        strand_read_depths,
+    }
+}
+
+/// The byte source behind a handle's reader.
+///
+/// Only uncompressed and bgzf files can ever be seeked; plain gzip and stdin are forward-only.
+enum VcfInput {
+    File(BufReader<File>),
+    Bgzf(bgzf::Reader<File>),
+    Gzip(Box<BufReader<MultiGzDecoder<File>>>),
+    Mmap(Cursor<Mmap>),
+    /// A copy of an Elixir binary, from `get_handle_from_binary`.
+    Memory(Cursor<Vec<u8>>),
+    Stdin(BufReader<Stdin>),
+}
+
+/// Reads the gzip magic bytes and, for bgzf, the `BC` extra subfield to tell `:bgzf`, `:gzip`
+/// and `:none` apart, leaving the file at its start.
+fn detect_compression(file: &mut File) -> std::io::Result<Atom> {
+    let mut magic = Vec::with_capacity(14);
+    file.take(14).read_to_end(&mut magic)?;
+    file.seek(SeekFrom::Start(0))?;
+
+    let is_gzip = magic.starts_with(&[0x1f, 0x8b]);
+    let is_bgzf = is_gzip && magic.len() == 14 && magic[3] & 0x04 != 0 && magic[12..] == *b"BC";
+    if is_bgzf {
+        Ok(atoms::bgzf())
+    } else if is_gzip {
+        Ok(atoms::gzip())
+    } else {
+        Ok(atoms::none())
+    }
+}
+
+impl Read for VcfInput {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        match self {
+            VcfInput::File(reader) => reader.read(buf),
+            VcfInput::Bgzf(reader) => reader.read(buf),
+            VcfInput::Gzip(reader) => reader.read(buf),
+            VcfInput::Mmap(reader) => reader.read(buf),
+            VcfInput::Memory(reader) => reader.read(buf),
+            VcfInput::Stdin(reader) => reader.read(buf),
+        }
+    }
+}
+
+impl BufRead for VcfInput {
+    fn fill_buf(&mut self) -> std::io::Result<&[u8]> {
+        match self {
+            VcfInput::File(reader) => reader.fill_buf(),
+            VcfInput::Bgzf(reader) => reader.fill_buf(),
+            VcfInput::Gzip(reader) => reader.fill_buf(),
+            VcfInput::Mmap(reader) => reader.fill_buf(),
+            VcfInput::Memory(reader) => reader.fill_buf(),
+            VcfInput::Stdin(reader) => reader.fill_buf(),
+        }
+    }
+
+    fn consume(&mut self, amt: usize) {
+        match self {
+            VcfInput::File(reader) => reader.consume(amt),
+            VcfInput::Bgzf(reader) => reader.consume(amt),
+            VcfInput::Gzip(reader) => reader.consume(amt),
+            VcfInput::Mmap(reader) => reader.consume(amt),
+            VcfInput::Memory(reader) => reader.consume(amt),
+            VcfInput::Stdin(reader) => reader.consume(amt),
+        }
+    }
+}
+
+impl VcfInput {
+    /// Picks a decoder from the gzip magic bytes and, for bgzf, the `BC` extra subfield.
+    fn from_file(mut file: File, options: HandleOptions) -> std::io::Result<Self> {
+        let compression = detect_compression(&mut file)?;
+        Ok(VcfInput::with_compression(file, compression, options))
+    }
+
+    /// Decodes `file` as `:bgzf`, `:gzip` or, for anything else, uncompressed.
+    fn with_compression(file: File, compression: Atom, options: HandleOptions) -> Self {
+        if compression == atoms::bgzf() {
+            VcfInput::Bgzf(bgzf::Reader::new(file))
+        } else if compression == atoms::gzip() {
+            VcfInput::Gzip(Box::new(BufReader::with_capacity(
+                options.buffer_size,
+                MultiGzDecoder::new(file),
+            )))
+        } else {
+            VcfInput::File(BufReader::with_capacity(options.buffer_size, file))
+        }
+    }
+
+    /// `:bcf` if the decoded content starts with the BCF magic, otherwise `:vcf`. Nothing is
+    /// consumed.
+    fn content_format(&mut self) -> std::io::Result<Atom> {
+        match self.fill_buf()?.starts_with(b"BCF") {
+            true => Ok(atoms::bcf()),
+            false => Ok(atoms::vcf()),
+        }
+    }
+
+    /// Moves back to the very start of the input, i.e. before the header.
+    fn seek_to_start(&mut self) -> Option<std::io::Result<u64>> {
+        match self {
+            VcfInput::File(reader) => Some(reader.seek(SeekFrom::Start(0))),
+            VcfInput::Bgzf(reader) => {
+                Some(reader.seek(bgzf::VirtualPosition::default()).map(u64::from))
+            }
+            VcfInput::Gzip(_reader) => None,
+            VcfInput::Mmap(reader) => Some(reader.seek(SeekFrom::Start(0))),
+            VcfInput::Memory(reader) => Some(reader.seek(SeekFrom::Start(0))),
+            VcfInput::Stdin(_reader) => None,
+        }
+    }
+
+    /// The offset of the next read (a virtual position for bgzf), or `None` for forward-only input.
+    fn position(&mut self) -> Option<std::io::Result<u64>> {
+        match self {
+            VcfInput::File(reader) => Some(reader.stream_position()),
+            VcfInput::Bgzf(reader) => Some(Ok(u64::from(reader.virtual_position()))),
+            VcfInput::Gzip(_reader) => None,
+            VcfInput::Mmap(reader) => Some(Ok(reader.position())),
+            VcfInput::Memory(reader) => Some(Ok(reader.position())),
+            VcfInput::Stdin(_reader) => None,
+        }
+    }
+
+    /// The byte offset of the next read in the file itself, so compressed for bgzf.
+    fn byte_offset(&mut self) -> Option<std::io::Result<u64>> {
+        match self {
+            VcfInput::File(reader) => Some(reader.stream_position()),
+            VcfInput::Bgzf(reader) => Some(Ok(reader.virtual_position().compressed())),
+            VcfInput::Gzip(_reader) => None,
+            VcfInput::Mmap(reader) => Some(Ok(reader.position())),
+            VcfInput::Memory(reader) => Some(Ok(reader.position())),
+            VcfInput::Stdin(_reader) => None,
+        }
+    }
+
+    /// The length of the file in the units of `byte_offset`.
+    fn byte_len(&self) -> Option<std::io::Result<u64>> {
+        match self {
+            VcfInput::File(reader) => Some(reader.get_ref().metadata().map(|meta| meta.len())),
+            VcfInput::Bgzf(reader) => Some(reader.get_ref().metadata().map(|meta| meta.len())),
+            VcfInput::Gzip(_reader) => None,
+            VcfInput::Mmap(reader) => Some(Ok(reader.get_ref().len() as u64)),
+            VcfInput::Memory(reader) => Some(Ok(reader.get_ref().len() as u64)),
+            VcfInput::Stdin(_reader) => None,
+        }
+    }
+
+    /// Moves to an offset previously reported by `position`.
+    fn seek_to(&mut self, position: u64) -> Option<std::io::Result<u64>> {
+        match self {
+            VcfInput::File(reader) => Some(reader.seek(SeekFrom::Start(position))),
+            VcfInput::Bgzf(reader) => Some(
+                reader
+                    .seek(bgzf::VirtualPosition::from(position))
+                    .map(u64::from),
+            ),
+            VcfInput::Gzip(_reader) => None,
+            VcfInput::Mmap(reader) => Some(reader.seek(SeekFrom::Start(position))),
+            VcfInput::Memory(reader) => Some(reader.seek(SeekFrom::Start(position))),
+            VcfInput::Stdin(_reader) => None,
+        }
+    }
+}
+
+/// Locks `mutex`, taking the lock over if a panic poisoned it.
+///
+/// A panic while a lock is held poisons it. What it guards is then at worst a stream stopped
+/// mid-line, no worse off than after an I/O error, so later calls carry on instead of panicking
+/// in turn and leaving the handle unusable for good.
+fn lock<T>(mutex: &sync::Mutex<T>) -> sync::MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(sync::PoisonError::into_inner)
+}
+
+/// Like `lock`, for reading an `RwLock`.
+fn read_lock<T>(lock: &sync::RwLock<T>) -> sync::RwLockReadGuard<'_, T> {
+    lock.read().unwrap_or_else(sync::PoisonError::into_inner)
+}
+
+/// Like `lock`, for writing an `RwLock`.
+fn write_lock<T>(lock: &sync::RwLock<T>) -> sync::RwLockWriteGuard<'_, T> {
+    lock.write().unwrap_or_else(sync::PoisonError::into_inner)
+}
+
+/// How a handle's input is compressed, reported as `:none`, `:bgzf` or `:gzip`.
+#[derive(Clone, Copy)]
+enum Compression {
+    None,
+    Bgzf,
+    Gzip,
+}
+
+impl Compression {
+    fn to_atom(self) -> Atom {
+        match self {
+            Compression::None => atoms::none(),
+            Compression::Bgzf => atoms::bgzf(),
+            Compression::Gzip => atoms::gzip(),
+        }
     }
 }
 
 struct VcfHandle {
-    pub header: sync::Mutex<vcf::Header>,
-    pub stream: sync::Mutex<vcf::Reader<BufReader<File>>>,
+    /// Read-locked by everything except `reload_header` and `set_parse_header`, so readers never
+    /// wait on each other.
+    pub header: sync::RwLock<vcf::Header>,
+    pub stream: sync::Mutex<vcf::Reader<VcfInput>>,
+    /// Physical lines consumed so far, header included; only updated with `stream` held.
+    pub line: AtomicUsize,
+    /// Cleared when a seek skips lines uncounted; records then report `line` as 0 until a rewind.
+    pub line_known: AtomicBool,
+    /// Lines in the header and the `byte_offset` just past it, set wherever the header is read.
+    pub header_lines: AtomicUsize,
+    pub data_offset: AtomicU64,
+    /// The file the handle was opened from; `None` for stdin or a binary.
+    pub path: Option<String>,
+    /// Whether the input is bgzf, recorded at open so queries never touch `stream`.
+    pub bgzf: bool,
+    /// As detected at open, so `handle_info` never waits on `stream` either.
+    pub compression: Compression,
+    /// The tabix index next to `path`, loaded by the first region query.
+    pub index: sync::Mutex<Option<sync::Arc<tabix::Index>>>,
+    /// The `missing:` option the handle was opened with.
+    pub missing: MissingValue,
+    /// The `max_field_len:` option the handle was opened with.
+    pub max_field_len: Option<usize>,
+}
+
+impl VcfHandle {
+    /// The options reads start from when a call passes none of its own.
+    fn read_options(&self) -> ReadOptions {
+        ReadOptions {
+            missing: self.missing,
+            max_field_len: self.max_field_len,
+            ..ReadOptions::default()
+        }
+    }
+
+    /// Records that `stream` has just read a header of `header_lines` lines.
+    fn mark_data_start(&self, stream: &mut vcf::Reader<VcfInput>, header_lines: usize) {
+        let data_offset = match stream.get_mut().byte_offset() {
+            Some(Ok(offset)) => offset,
+            _ => 0,
+        };
+        self.line.store(header_lines, Ordering::Relaxed);
+        self.line_known.store(true, Ordering::Relaxed);
+        self.header_lines.store(header_lines, Ordering::Relaxed);
+        self.data_offset.store(data_offset, Ordering::Relaxed);
+    }
+
+    /// Counts one more consumed line and returns its number, or 0 if the count has been lost.
+    fn next_line(&self) -> usize {
+        let line = self.line.fetch_add(1, Ordering::Relaxed) + 1;
+        if self.line_known.load(Ordering::Relaxed) {
+            line
+        } else {
+            0
+        }
+    }
+
+    /// Returns the tabix index next to `path`, reading it on first use.
+    fn tabix_index(&self, path: &str) -> std::io::Result<sync::Arc<tabix::Index>> {
+        let mut index = lock(&self.index);
+        if index.is_none() {
+            *index = Some(sync::Arc::new(tabix::read(index_path(path))?));
+        }
+        Ok(index.clone().unwrap())
+    }
+}
+
+/// The byte sink behind a writer handle.
+enum VcfOutput {
+    File(BufWriter<File>),
+    /// Picked for paths ending in `.gz`, so the output can be indexed.
+    Bgzf(bgzf::Writer<File>),
+    /// Left behind by `close_writer`.
+    Closed,
+}
+
+impl VcfOutput {
+    /// Flushes what is buffered, appending the end-of-file block to bgzf output.
+    fn finish(self) -> std::io::Result<()> {
+        match self {
+            VcfOutput::File(mut file) => file.flush(),
+            VcfOutput::Bgzf(bgzf) => bgzf.finish().map(|_| ()),
+            VcfOutput::Closed => Ok(()),
+        }
+    }
+}
+
+impl Write for VcfOutput {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self {
+            VcfOutput::File(writer) => writer.write(buf),
+            VcfOutput::Bgzf(writer) => writer.write(buf),
+            VcfOutput::Closed => Err(IoError::from(IoErrorKind::BrokenPipe)),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self {
+            VcfOutput::File(writer) => writer.flush(),
+            VcfOutput::Bgzf(writer) => writer.flush(),
+            VcfOutput::Closed => Ok(()),
+        }
+    }
+}
+
+struct VcfWriterHandle {
+    pub header: vcf::Header,
+    pub stream: sync::Mutex<vcf::Writer<VcfOutput>>,
+}
+
+impl VcfWriterHandle {
+    /// Locks the output, failing with `:closed` once `close_writer` has run.
+    fn open_stream(&self) -> Result<sync::MutexGuard<'_, vcf::Writer<VcfOutput>>, RustlerError> {
+        let stream = lock(&self.stream);
+        match stream.get_ref() {
+            VcfOutput::Closed => Err(RustlerError::Term(Box::new(atoms::closed()))),
+            _ => Ok(stream),
+        }
+    }
 }
 
 #[derive(rustler::NifStruct)]
@@ -91,6 +478,15 @@ struct VcfInfo<'a> {
     pub description: String,
 }
 
+#[derive(rustler::NifStruct)]
+#[module = "Noodlex.Vcf.Header.Format"]
+struct VcfFormat<'a> {
+    pub id: String,
+    pub number: Term<'a>,
+    pub type_: Atom,
+    pub description: String,
+}
+
 #[derive(rustler::NifStruct)]
 #[module = "Noodlex.Vcf.Header.Filter"]
 struct VcfFilter {
@@ -110,22 +506,140 @@ struct FileFormat {
 struct VcfHeader<'a> {
     pub fileformat: FileFormat,
     pub infos: Term<'a>,
+    pub formats: Term<'a>,
     pub filters: Term<'a>,
+    /// Contig name to its declared length, `nil` when the `##contig` line gives none.
+    pub contigs: Term<'a>,
+    pub other: Term<'a>,
+    pub pedigrees: Term<'a>,
+    pub samples_meta: Term<'a>,
+    pub sample_names: Vec<String>,
+}
+
+/// A symbolic alternate allele's `##ALT` header record.
+#[derive(rustler::NifMap)]
+struct AltDescription {
+    id: String,
+    description: String,
+}
+
+/// The 1-based, inclusive span a record covers on its contig.
+#[derive(rustler::NifStruct)]
+#[module = "Noodlex.Vcf.Position"]
+struct VcfPosition {
+    pub chrom: String,
+    pub start: usize,
+    /// `END` when the record has an integer one, otherwise the last reference base.
+    pub end: usize,
 }
 
 #[derive(rustler::NifStruct)]
 #[module = "Noodlex.Vcf.Record"]
 struct VcfRecord<'a> {
+    pub line: usize,
     pub chromosome: String,
+    /// The same as `interval.start`, kept as a plain integer.
     pub position: usize,
+    pub interval: VcfPosition,
     pub ids: Vec<String>,
     pub reference_bases: String,
-    pub alternate_bases: String,
-    pub quality_score: Option<f32>,
+    pub alternate_bases: Vec<String>,
+    /// `alternate_bases` with each symbolic allele declared by a `##ALT` header record as
+    /// `%{id: id, description: description}`; other alleles stay strings.
+    pub resolved_alternate_bases: Vec<Term<'a>>,
+    pub alt_kinds: Vec<Atom>,
+    pub variant_type: Vec<Atom>,
+    pub breakends: Vec<Option<VcfBreakend>>,
+    pub quality_score: Term<'a>,
     pub filters: VcfRecordFilters,
+    /// `filters` boiled down to PASS or not; `nil` when FILTER is missing.
+    pub passed: Option<bool>,
     pub info: Term<'a>,
+    pub reserved_info: Term<'a>,
+    /// `Number=A` and `Number=R` INFO values keyed by the allele each belongs to.
+    pub allele_info: Term<'a>,
+    /// `CIPOS` as `{lower, upper}`, or `nil` when absent or not two integers.
+    pub position_confidence_intervals: Option<(i32, i32)>,
+    /// `CIEND` as `{lower, upper}`, or `nil` when absent or not two integers.
+    pub end_confidence_intervals: Option<(i32, i32)>,
+    /// FORMAT keys in the record's own column order, which may differ from the header's.
     pub format: Vec<String>,
     pub genotypes: Term<'a>,
+    pub ploidy: Term<'a>,
+}
+
+/// How a handle was opened, for diagnostics.
+#[derive(rustler::NifMap)]
+struct HandleInfo<'a> {
+    /// The path the handle reads, `:stdin` or `:binary`.
+    pub source: Term<'a>,
+    pub compression: Atom,
+    /// Always `:vcf`; BCF input is not supported.
+    pub format: Atom,
+    /// Whether a tabix index has been loaded, which happens on the first call that needs one.
+    pub index_loaded: bool,
+}
+
+/// What `read_index` reports about a tabix or CSI index.
+#[derive(rustler::NifMap)]
+struct IndexMetadata<'a> {
+    /// `:tbi` or `:csi`.
+    pub kind: Atom,
+    /// `:vcf`, `:sam` or `:generic`; `nil` for a CSI index without tabix settings.
+    pub format: Option<Atom>,
+    /// 1-based columns holding the contig, start and (if any) end of each record.
+    pub reference_sequence_name_index: Option<usize>,
+    pub start_position_index: Option<usize>,
+    pub end_position_index: Option<usize>,
+    pub line_comment_prefix: Option<String>,
+    pub line_skip_count: Option<u32>,
+    /// Indexed contigs in index order.
+    pub contigs: Vec<String>,
+    /// Records per contig, `nil` where the index keeps no count.
+    pub record_counts: Term<'a>,
+}
+
+/// How often two samples' `GT` calls agree across the records `genotype_concordance` reads.
+#[derive(Default, rustler::NifMap)]
+struct GenotypeConcordance {
+    pub matching: u64,
+    pub mismatching: u64,
+    pub both_missing: u64,
+    /// Sites where only one of the two samples has a call; these count towards neither side of
+    /// the ratio.
+    pub one_missing: u64,
+    /// `matching / (matching + mismatching)`, or `nil` when no site has two calls.
+    pub concordance: Option<f64>,
+}
+
+/// What `region_summary` reports about the records overlapping a region.
+#[derive(rustler::NifMap)]
+struct RegionSummary<'a> {
+    pub record_count: u64,
+    /// Alternate alleles per `variant_type` atom, e.g. `%{snv: 12, deletion: 3}`; a record with
+    /// several alternate alleles counts once for each.
+    pub variant_types: Term<'a>,
+    /// Mean `QUAL` of the records that have one, or `nil` when none does.
+    pub mean_quality_score: Option<f64>,
+}
+
+#[derive(rustler::NifStruct)]
+#[module = "Noodlex.Vcf.Record.Genotype"]
+struct VcfGenotype {
+    pub alleles: Vec<Option<usize>>,
+    pub phased: bool,
+}
+
+/// The mate of a breakend allele, e.g. `G]17:198982]`.
+#[derive(Clone, rustler::NifStruct)]
+#[module = "Noodlex.Vcf.Record.Breakend"]
+struct VcfBreakend {
+    pub mate_chrom: String,
+    pub mate_pos: usize,
+    /// `"["` when the joined sequence extends right of the mate position, `"]"` when left.
+    pub bracket: String,
+    /// `:after` when the mate is joined after the record's bases (`t[p[`, `t]p]`), else `:before`.
+    pub join: Atom,
 }
 
 #[derive(rustler::NifTaggedEnum)]
@@ -135,8 +649,261 @@ enum VcfRecordFilters {
     Fail(Vec<String>),
 }
 
+/// How a missing (`.`) QUAL, typed INFO or FORMAT value reaches Elixir.
+#[derive(Clone, Copy, Default)]
+enum MissingValue {
+    #[default]
+    Nil,
+    /// `:missing`
+    MissingAtom,
+    /// `""`
+    EmptyString,
+}
+
+impl MissingValue {
+    /// Accepts `nil`, `:missing` or `""`.
+    fn decode(term: Term) -> Result<Self, RustlerError> {
+        if term.get_type() == TermType::Binary {
+            return match term.decode::<String>()?.is_empty() {
+                true => Ok(MissingValue::EmptyString),
+                false => Err(RustlerError::BadArg),
+            };
+        }
+        match term.decode::<Option<Atom>>()? {
+            None => Ok(MissingValue::Nil),
+            Some(atom) if atom == atoms::missing() => Ok(MissingValue::MissingAtom),
+            Some(_atom) => Err(RustlerError::BadArg),
+        }
+    }
+
+    fn encode<'a>(self, env: Env<'a>) -> Term<'a> {
+        match self {
+            MissingValue::Nil => None::<()>.encode(env),
+            MissingValue::MissingAtom => atoms::missing().encode(env),
+            MissingValue::EmptyString => "".encode(env),
+        }
+    }
+
+    fn or_encode<'a, T: Encoder>(self, env: Env<'a>, value: Option<T>) -> Term<'a> {
+        match value {
+            Some(value) => value.encode(env),
+            None => self.encode(env),
+        }
+    }
+
+    /// Encodes an array value, swapping each missing element for the sentinel.
+    fn encode_list<'a, T: Encoder>(
+        self,
+        env: Env<'a>,
+        values: impl Iterator<Item = Option<T>>,
+    ) -> Term<'a> {
+        values
+            .map(|value| self.or_encode(env, value))
+            .collect::<Vec<_>>()
+            .encode(env)
+    }
+}
+
+/// Per-call read settings decoded from an Elixir keyword list.
+#[derive(Clone, Default)]
+struct ReadOptions {
+    /// Fill in `NS`, `AN` and `AC` from the genotypes when the INFO column lacks them.
+    compute_stats: bool,
+    /// Fill in `AF` as `AC / AN` from the genotypes when the INFO column lacks it.
+    compute_allele_frequencies: bool,
+    /// INFO keys to extract; `None` extracts every key.
+    info: Option<Vec<String>>,
+    /// FORMAT keys to extract; `None` extracts every key.
+    format: Option<Vec<String>>,
+    /// Stands in for missing values; defaults to the handle's setting.
+    missing: MissingValue,
+    /// Return `info` as `{key, value}` pairs in column order instead of a map.
+    ordered_info: bool,
+    /// What a record line that fails to parse does to the read.
+    on_error: OnError,
+    /// Longest column, in bytes, a record may have before it is rejected unparsed; `None` is
+    /// unlimited.
+    max_field_len: Option<usize>,
+}
+
+/// The `on_error:` read option.
+#[derive(Clone, Copy, Default, PartialEq)]
+enum OnError {
+    /// Fail the whole call with the line's `{:parse, line, reason}`.
+    #[default]
+    Stop,
+    /// Drop the line and read on.
+    Skip,
+    /// Read on, and return `{line, reason}` for each bad line alongside the records.
+    Collect,
+}
+
+impl OnError {
+    fn decode(term: Term) -> Result<Self, RustlerError> {
+        let atom: Atom = term.decode()?;
+        if atom == atoms::stop() {
+            Ok(OnError::Stop)
+        } else if atom == atoms::skip() {
+            Ok(OnError::Skip)
+        } else if atom == atoms::collect() {
+            Ok(OnError::Collect)
+        } else {
+            Err(RustlerError::BadArg)
+        }
+    }
+}
+
+impl ReadOptions {
+    fn includes_info(&self, key: &str) -> bool {
+        self.info
+            .as_ref()
+            .is_none_or(|keys| keys.iter().any(|k| k == key))
+    }
+
+    fn includes_format(&self, key: &str) -> bool {
+        self.format
+            .as_ref()
+            .is_none_or(|keys| keys.iter().any(|k| k == key))
+    }
+
+    /// Finds the first column of `line` longer than `max_field_len`, if any, so the record can be
+    /// turned down before anything is built from it.
+    fn oversized_field(&self, header: &vcf::Header, line: &str) -> Option<(String, usize)> {
+        const COLUMNS: [&str; 9] = [
+            "CHROM", "POS", "ID", "REF", "ALT", "QUAL", "FILTER", "INFO", "FORMAT",
+        ];
+
+        let limit = self.max_field_len?;
+        let (i, field) = line
+            .trim_end_matches(&['\n', '\r'][..])
+            .split('\t')
+            .enumerate()
+            .find(|(_i, field)| field.len() > limit)?;
+        let column = match COLUMNS.get(i) {
+            Some(name) => name.to_string(),
+            None => header
+                .sample_names()
+                .get_index(i - COLUMNS.len())
+                .cloned()
+                .unwrap_or_else(|| (i + 1).to_string()),
+        };
+        Some((column, field.len()))
+    }
+
+    /// Checks `line` against `max_field_len`, failing with `{:field_too_long, line, column}`.
+    fn check_field_lengths(
+        &self,
+        header: &vcf::Header,
+        line: usize,
+        buf: &str,
+    ) -> Result<(), RustlerError> {
+        match self.oversized_field(header, buf) {
+            Some((column, _len)) => Err(RustlerError::Term(Box::new((
+                atoms::field_too_long(),
+                line,
+                column,
+            )))),
+            None => Ok(()),
+        }
+    }
+}
+
+fn decode_read_options(
+    handle: &VcfHandle,
+    opts: Vec<(Atom, Term)>,
+) -> Result<ReadOptions, RustlerError> {
+    let mut options = handle.read_options();
+    for (key, value) in opts {
+        if key == atoms::compute_stats() {
+            options.compute_stats = value.decode()?;
+        } else if key == atoms::compute_allele_frequencies() {
+            options.compute_allele_frequencies = value.decode()?;
+        } else if key == atoms::info() {
+            options.info = Some(value.decode()?);
+        } else if key == atoms::format() {
+            options.format = Some(value.decode()?);
+        } else if key == atoms::missing() {
+            options.missing = MissingValue::decode(value)?;
+        } else if key == atoms::ordered_info() {
+            options.ordered_info = value.decode()?;
+        } else if key == atoms::on_error() {
+            options.on_error = OnError::decode(value)?;
+        } else if key == atoms::max_field_len() {
+            options.max_field_len = value.decode()?;
+        } else {
+            return Err(RustlerError::BadArg);
+        }
+    }
+    Ok(options)
+}
+
+/// Settings for opening a handle, decoded from an Elixir keyword list.
+#[derive(Clone, Copy)]
+struct HandleOptions {
+    /// Capacity of the read buffer for uncompressed and plain gzip files (bgzf buffers by block).
+    ///
+    /// The 8 KiB default matches `BufReader::new`; long sequential scans of big files may
+    /// benefit from something in the 64 KiB to 1 MiB range.
+    buffer_size: usize,
+    /// How reads on the handle represent missing values unless told otherwise.
+    missing: MissingValue,
+    /// Default `max_field_len:` for reads on the handle.
+    max_field_len: Option<usize>,
+    /// `:none`, `:bgzf` or `:gzip` to use instead of the one the magic bytes suggest.
+    compression: Option<Atom>,
+    /// `:vcf` or `:bcf`, which the decoded content must match.
+    format: Option<Atom>,
+}
+
+impl Default for HandleOptions {
+    fn default() -> Self {
+        HandleOptions {
+            buffer_size: 8 * 1024,
+            missing: MissingValue::Nil,
+            max_field_len: None,
+            compression: None,
+            format: None,
+        }
+    }
+}
+
+fn decode_handle_options(opts: Vec<(Atom, Term)>) -> Result<HandleOptions, RustlerError> {
+    let mut options = HandleOptions::default();
+    for (key, value) in opts {
+        if key == atoms::buffer_size() {
+            options.buffer_size = value.decode()?;
+        } else if key == atoms::missing() {
+            options.missing = MissingValue::decode(value)?;
+        } else if key == atoms::max_field_len() {
+            options.max_field_len = value.decode()?;
+        } else if key == atoms::compression() {
+            let compression: Atom = value.decode()?;
+            if compression != atoms::none()
+                && compression != atoms::bgzf()
+                && compression != atoms::gzip()
+            {
+                return Err(RustlerError::BadArg);
+            }
+            options.compression = Some(compression);
+        } else if key == atoms::format() {
+            let format: Atom = value.decode()?;
+            if format != atoms::vcf() && format != atoms::bcf() {
+                return Err(RustlerError::BadArg);
+            }
+            options.format = Some(format);
+        } else {
+            return Err(RustlerError::BadArg);
+        }
+    }
+    if options.buffer_size == 0 {
+        return Err(RustlerError::BadArg);
+    }
+    Ok(options)
+}
+
 fn load(env: rustler::Env, _info: rustler::Term) -> bool {
     rustler::resource!(VcfHandle, env);
+    rustler::resource!(VcfWriterHandle, env);
     true
 }
 
@@ -150,6 +917,40 @@ fn io_error_to_term(err: &IoError) -> Atom {
     }
 }
 
+fn parse_error(line: usize, err: impl std::fmt::Display) -> RustlerError {
+    RustlerError::Term(Box::new((atoms::parse(), line, err.to_string())))
+}
+
+/// Builds `{:header, line, reason}` for a header that failed to parse as a whole.
+fn header_error(raw_header: &str, err: vcf::header::ParseError) -> RustlerError {
+    let line = header_error_line(raw_header);
+    RustlerError::Term(Box::new((atoms::header(), line, err.to_string())))
+}
+
+/// Re-parses the header a line at a time to find the line that broke it.
+///
+/// Errors no single `##` line accounts for, such as a duplicate sample name, land on `#CHROM`.
+fn header_error_line(raw_header: &str) -> usize {
+    use std::convert::TryFrom;
+    use vcf::header::Record;
+
+    let mut lines = raw_header.lines();
+    let file_format = match lines.next().map(str::parse) {
+        Some(Ok(Record::FileFormat(file_format))) => file_format,
+        _ => return 1,
+    };
+    for (line, text) in (2..).zip(lines) {
+        if text.starts_with("#CHROM") {
+            return line;
+        }
+        match Record::try_from((file_format, text)) {
+            Ok(Record::FileFormat(_)) | Err(_) => return line,
+            Ok(_) => {}
+        }
+    }
+    raw_header.lines().count()
+}
+
 macro_rules! handle_io_error {
     ($e:expr) => {
         match $e {
@@ -159,229 +960,4122 @@ macro_rules! handle_io_error {
     };
 }
 
-#[rustler::nif]
-fn get_handle(path: String) -> Result<ResourceArc<VcfHandle>, RustlerError> {
-    let file_result = File::open(path.clone());
-    let file = handle_io_error!(file_result);
-    let reader = BufReader::new(file);
-    let mut vcf_reader = vcf::Reader::new(reader);
-    let header_result = vcf_reader.read_header();
-    let header = handle_io_error!(header_result);
-    match header.parse::<vcf::header::Header>() {
-        Ok(header) => {
-            let mutex = sync::Mutex::new(vcf_reader);
-            let header_mutex = sync::Mutex::new(header);
-            let resource_arc = ResourceArc::new(VcfHandle {
-                header: header_mutex,
-                stream: mutex,
-            });
+fn genotype_value_to_term<'a>(
+    env: Env<'a>,
+    value: &vcf::record::genotypes::genotype::field::Value,
+    missing: MissingValue,
+) -> Term<'a> {
+    use vcf::record::genotypes::genotype::field::Value;
 
-            Ok(resource_arc)
-        }
-        Err(err) => {
-            let error = format!("Error parsing header: {}", err);
-            Err(RustlerError::Term(Box::new(error)))
+    match value {
+        Value::Integer(n) => n.encode(env),
+        Value::Float(n) => n.encode(env),
+        Value::Character(c) => c.to_string().encode(env),
+        Value::String(s) => s.encode(env),
+        Value::IntegerArray(values) => missing.encode_list(env, values.iter().copied()),
+        Value::FloatArray(values) => missing.encode_list(env, values.iter().copied()),
+        Value::CharacterArray(values) => {
+            missing.encode_list(env, values.iter().map(|value| value.map(String::from)))
         }
+        Value::StringArray(values) => missing.encode_list(env, values.iter().map(Option::as_deref)),
     }
 }
 
-#[rustler::nif]
-fn get_header<'a>(
+/// Converts a parsed `GT` value; haploid calls have no separator and count as phased.
+fn genotype_call_to_struct(
+    genotype: &vcf::record::genotypes::genotype::field::value::Genotype,
+) -> VcfGenotype {
+    use vcf::record::genotypes::genotype::field::value::genotype::allele::Phasing;
+
+    VcfGenotype {
+        alleles: genotype.iter().map(|allele| allele.position()).collect(),
+        phased: genotype
+            .iter()
+            .skip(1)
+            .all(|allele| allele.phasing() == Some(Phasing::Phased)),
+    }
+}
+
+fn genotype_to_term<'a>(
     env: Env<'a>,
-    handle: ResourceArc<VcfHandle>,
-) -> Result<VcfHeader<'a>, RustlerError> {
-    let header = handle.header.lock().unwrap();
-    let fileformat = FileFormat {
-        major: header.file_format().major(),
-        minor: header.file_format().minor(),
-    };
-    let mut infos_vector = Vec::new();
-    for (key, value) in header.infos() {
-        let id = Atom::from_str(env, &key.to_string()).unwrap();
-        let number = match value.number() {
-            vcf::header::Number::Count(_count) => atoms::unknown().to_term(env),
-            vcf::header::Number::A => atoms::alternate_alleles().to_term(env),
-            vcf::header::Number::R => atoms::reference_and_alternate_alleles().to_term(env),
-            vcf::header::Number::G => atoms::genotypes().to_term(env),
-            vcf::header::Number::Unknown => atoms::unknown().to_term(env),
-        };
-        let type_ = match value.ty() {
-            vcf::header::info::ty::Type::Integer => atoms::integer(),
-            vcf::header::info::ty::Type::Float => atoms::float(),
-            vcf::header::info::ty::Type::Flag => atoms::flag(),
-            vcf::header::info::ty::Type::Character => atoms::character(),
-            vcf::header::info::ty::Type::String => atoms::string(),
-        };
-        let description = value.description().to_string();
-
-        infos_vector.push((
-            Atom::from_str(env, &key.to_string()).unwrap(),
-            VcfInfo {
-                id,
-                number,
-                type_,
-                description,
+    genotype: &vcf::record::genotypes::Genotype,
+    options: &ReadOptions,
+) -> Result<Term<'a>, RustlerError> {
+    let mut pairs = Vec::with_capacity(genotype.len());
+    for (key, field) in genotype.iter() {
+        if !options.includes_format(key.as_ref()) {
+            continue;
+        }
+        let value = match (key, field.value()) {
+            (_key, None) => options.missing.encode(env),
+            (vcf::header::format::Key::Genotype, Some(value)) => match genotype.genotype() {
+                Some(Ok(call)) => genotype_call_to_struct(&call).encode(env),
+                _ => genotype_value_to_term(env, value, options.missing),
             },
-        ));
+            (_key, Some(value)) => genotype_value_to_term(env, value, options.missing),
+        };
+        pairs.push((key.as_ref(), value));
     }
-    let mut filters_vector = Vec::new();
-    for (key, value) in header.filters() {
-        filters_vector.push((
-            key.to_string(),
-            VcfFilter {
-                id: key.to_string(),
-                description: value.description().to_string(),
-            },
-        ));
+    Term::map_from_pairs(env, &pairs)
+}
+
+fn alt_kind(
+    reference_bases: &vcf::record::ReferenceBases,
+    allele: &vcf::record::alternate_bases::Allele,
+) -> Atom {
+    use vcf::record::alternate_bases::Allele;
+
+    match allele {
+        Allele::Bases(bases) if bases.len() == 1 && reference_bases.len() == 1 => atoms::snv(),
+        Allele::Symbol(_symbol) => atoms::symbolic(),
+        Allele::Breakend(_breakend) => atoms::breakend(),
+        _ => atoms::other(),
     }
-    match (
-        Term::map_from_pairs(env, &infos_vector),
-        Term::map_from_pairs(env, &filters_vector),
-    ) {
-        (Ok(infos), Ok(filters)) => Ok(VcfHeader {
-            fileformat,
-            infos,
-            filters,
-        }),
-        _ => Err(RustlerError::Term(Box::new(atoms::error()))),
+}
+
+/// Parses the mate out of a breakend allele; single breakends such as `G.` have none.
+fn parse_breakend(allele: &str) -> Option<VcfBreakend> {
+    let (bracket, join) = match (allele.chars().next()?, allele.chars().last()?) {
+        (bracket @ ('[' | ']'), _) => (bracket, atoms::before()),
+        (_, bracket @ ('[' | ']')) => (bracket, atoms::after()),
+        _ => return None,
+    };
+    let start = allele.find(bracket)?;
+    let end = allele.rfind(bracket)?;
+    if start == end {
+        return None;
     }
+    let (mate_chrom, mate_pos) = allele[start + 1..end].rsplit_once(':')?;
+    Some(VcfBreakend {
+        // Contigs may be written in angle brackets, as in `C[<ctg1>:7[`.
+        mate_chrom: mate_chrom
+            .trim_start_matches('<')
+            .trim_end_matches('>')
+            .to_string(),
+        mate_pos: mate_pos.parse().ok()?,
+        bracket: bracket.to_string(),
+        join,
+    })
 }
 
-#[rustler::nif]
-fn get_record<'a>(env: Env<'a>, handle: ResourceArc<VcfHandle>) -> Result<VcfRecord, RustlerError> {
-    let mut buf = String::new();
-    let _bytes_read = handle.stream.lock().unwrap().read_record(&mut buf).unwrap();
-    let parsed_record = vcf::record::Record::try_from_str(&buf, &handle.header.lock().unwrap());
-    match (buf.is_empty(), parsed_record) {
-        (true, _) => Err(RustlerError::Term(Box::new(atoms::end_of_file()))),
-        (_is_empty, Ok(record)) => {
-            let chromosome = record.chromosome().to_string();
-            let position = record.position().into();
-            let ids = record.ids().iter().map(|id| id.to_string()).collect();
-            let reference_bases = record.reference_bases().to_string();
-            let alternate_bases = record.alternate_bases().to_string();
-            let quality_score = record.quality_score().map(f32::from).into();
-            let filters = match record.filters() {
-                Some(filters) => match filters {
-                    vcf::record::filters::Filters::Pass => VcfRecordFilters::Pass,
-                    vcf::record::filters::Filters::Fail(filters) => {
-                        VcfRecordFilters::Fail(filters.iter().map(|f| f.to_string()).collect())
-                    }
-                },
-                None => VcfRecordFilters::None,
-            };
-            let info_keys: Vec<String> = record.info().keys().map(|k| k.to_string()).collect();
-            let info_values: Vec<String> = record.info().values().map(|v| v.to_string()).collect();
-            let info = Term::map_from_arrays(env, &info_keys, &info_values)?;
-            let format = record.format().iter().map(|k| k.to_string()).collect();
-            let genotypes_pairs: Vec<(String, String)> = record
-                .genotypes()
-                .deref()
-                .iter()
-                .map(|v| {
-                    let map = v.deref();
-                    let keys = map.keys().map(|k| k.to_string());
-                    let values = map.values().map(|v| v.to_string());
-                    keys.zip(values)
-                })
-                .flatten()
-                .unique_by(|(k, _v)| k.to_string())
-                .collect();
-            let genotypes = Term::map_from_pairs(env, &genotypes_pairs)?;
-
-            return Ok(VcfRecord {
-                chromosome,
-                position,
-                ids,
-                reference_bases,
-                alternate_bases,
-                quality_score,
-                filters,
-                info,
-                format,
-                genotypes,
-            });
-        }
-        (_is_empty, Err(err)) => Err(RustlerError::Term(Box::new(err.to_string()))),
+/// Classifies an allele by comparing its length to the reference; only plain bases qualify.
+fn allele_variant_type(
+    reference_bases: &vcf::record::ReferenceBases,
+    allele: &vcf::record::alternate_bases::Allele,
+) -> Atom {
+    use std::cmp::Ordering;
+    use vcf::record::alternate_bases::Allele;
+
+    match allele {
+        Allele::Bases(bases) => match bases.len().cmp(&reference_bases.len()) {
+            Ordering::Greater => atoms::insertion(),
+            Ordering::Less => atoms::deletion(),
+            Ordering::Equal if bases.len() == 1 => atoms::snv(),
+            Ordering::Equal => atoms::mnv(),
+        },
+        _ => atoms::other(),
     }
 }
 
-#[rustler::nif]
-fn get_records<'a>(
+fn reserved_info_atom(key: &vcf::header::info::Key) -> Option<Atom> {
+    use vcf::header::info::Key;
+
+    let atom = match key {
+        Key::AncestralAllele => atoms::ancestral_allele(),
+        Key::AlleleCount => atoms::allele_count(),
+        Key::TotalReadDepths => atoms::total_read_depths(),
+        Key::ForwardStrandReadDepths => atoms::forward_strand_read_depths(),
+        Key::ReverseStrandReadDepths => atoms::reverse_strand_read_depths(),
+        Key::AlleleFrequencies => atoms::allele_frequencies(),
+        Key::TotalAlleleCount => atoms::total_allele_count(),
+        Key::BaseQuality => atoms::base_quality(),
+        Key::Cigar => atoms::cigar(),
+        Key::IsInDbSnp => atoms::is_in_db_snp(),
+        Key::TotalDepth => atoms::total_depth(),
+        Key::IsInHapMap2 => atoms::is_in_hap_map2(),
+        Key::IsInHapMap3 => atoms::is_in_hap_map3(),
+        Key::MappingQuality => atoms::mapping_quality(),
+        Key::ZeroMappingQualityCount => atoms::zero_mapping_quality_count(),
+        Key::SamplesWithDataCount => atoms::samples_with_data_count(),
+        Key::StrandBias => atoms::strand_bias(),
+        Key::IsSomaticMutation => atoms::is_somatic_mutation(),
+        Key::IsValidated => atoms::is_validated(),
+        Key::IsIn1000Genomes => atoms::is_in_1000_genomes(),
+        Key::IsImprecise => atoms::is_imprecise(),
+        Key::IsNovel => atoms::is_novel(),
+        Key::EndPosition => atoms::end_position(),
+        Key::SvType => atoms::sv_type(),
+        Key::SvLengths => atoms::sv_lengths(),
+        Key::PositionConfidenceIntervals => atoms::position_confidence_intervals(),
+        Key::EndConfidenceIntervals => atoms::end_confidence_intervals(),
+        Key::MicrohomologyLengths => atoms::microhomology_lengths(),
+        Key::MicrohomologySequences => atoms::microhomology_sequences(),
+        Key::BreakpointIds => atoms::breakpoint_ids(),
+        Key::MobileElementInfo => atoms::mobile_element_info(),
+        Key::MobileElementTransductionInfo => atoms::mobile_element_transduction_info(),
+        Key::DbvId => atoms::dbv_id(),
+        Key::DbVarId => atoms::db_var_id(),
+        Key::DbRipId => atoms::db_rip_id(),
+        Key::MateBreakendIds => atoms::mate_breakend_ids(),
+        Key::PartnerBreakendId => atoms::partner_breakend_id(),
+        Key::BreakendEventId => atoms::breakend_event_id(),
+        Key::BreakendConfidenceIntervals => atoms::breakend_confidence_intervals(),
+        Key::AdjacentReadDepths => atoms::adjacent_read_depths(),
+        Key::BreakendCopyNumber => atoms::breakend_copy_number(),
+        Key::AdjacentCopyNumber => atoms::adjacent_copy_number(),
+        Key::CopyNumberConfidenceIntervals => atoms::copy_number_confidence_intervals(),
+        Key::AdjacentCopyNumberConfidenceIntervals => {
+            atoms::adjacent_copy_number_confidence_intervals()
+        }
+        Key::Other(_key) => return None,
+    };
+    Some(atom)
+}
+
+fn info_value_to_term<'a>(
     env: Env<'a>,
-    handle: ResourceArc<VcfHandle>,
-    count: usize,
-) -> Result<Vec<VcfRecord>, RustlerError> {
-    let mut buf = String::new();
-    let mut result_vector = Vec::new();
-    result_vector.reserve(count);
-    let mut first_read = true;
-    let mut rustler_error = None;
-    let mut stream = handle.stream.lock().unwrap();
-    let mut end_of_file = false;
-    let header = handle.header.lock().unwrap();
-
-    while first_read || rustler_error.is_none() && result_vector.len() < count && !end_of_file {
-        first_read = false;
-        let _bytes_read = stream.read_record(&mut buf).unwrap();
-        let parsed_record = vcf::record::Record::try_from_str(&buf, &header);
-        match (buf.is_empty(), parsed_record) {
-            (true, _) => {
-                end_of_file = true;
-            }
-            (_is_empty, Ok(record)) => {
-                let chromosome = record.chromosome().to_string();
-                let position = record.position().into();
-                let ids = record.ids().iter().map(|id| id.to_string()).collect();
-                let reference_bases = record.reference_bases().to_string();
-                let alternate_bases = record.alternate_bases().to_string();
-                let quality_score = record.quality_score().map(f32::from).into();
-                let filters = match record.filters() {
-                    Some(filters) => match filters {
-                        vcf::record::filters::Filters::Pass => VcfRecordFilters::Pass,
-                        vcf::record::filters::Filters::Fail(filters) => {
-                            VcfRecordFilters::Fail(filters.iter().map(|f| f.to_string()).collect())
-                        }
-                    },
-                    None => VcfRecordFilters::None,
-                };
-                let info_keys: Vec<String> = record.info().keys().map(|k| k.to_string()).collect();
-                let info_values: Vec<String> =
-                    record.info().values().map(|v| v.to_string()).collect();
-                let info = Term::map_from_arrays(env, &info_keys, &info_values).unwrap();
-                let format = record.format().iter().map(|k| k.to_string()).collect();
-                let genotypes_pairs: Vec<(String, String)> = Vec::new();
-                let genotypes = Term::map_from_pairs(env, &genotypes_pairs).unwrap();
-
-                result_vector.push(VcfRecord {
-                    chromosome,
-                    position,
-                    ids,
-                    reference_bases,
-                    alternate_bases,
-                    quality_score,
-                    filters,
-                    info,
-                    format,
-                    genotypes,
-                });
-                buf.clear();
-            }
-            (_is_empty, Err(err)) => {
-                println!("err: {} | buf: {}", err, buf);
-                rustler_error = Some(RustlerError::Term(Box::new(err.to_string())))
-            }
+    value: &vcf::record::info::field::Value,
+    missing: MissingValue,
+) -> Term<'a> {
+    use vcf::record::info::field::Value;
+
+    match value {
+        Value::Integer(n) => n.encode(env),
+        Value::Float(n) => n.encode(env),
+        Value::Flag => true.encode(env),
+        Value::Character(c) => c.to_string().encode(env),
+        Value::String(s) => s.encode(env),
+        Value::IntegerArray(values) => missing.encode_list(env, values.iter().copied()),
+        Value::FloatArray(values) => missing.encode_list(env, values.iter().copied()),
+        Value::CharacterArray(values) => {
+            missing.encode_list(env, values.iter().map(|value| value.map(String::from)))
         }
+        Value::StringArray(values) => missing.encode_list(env, values.iter().map(Option::as_deref)),
     }
+}
+
+/// Reads a `CIPOS`-style INFO value holding exactly two integers.
+fn confidence_interval(
+    record: &vcf::record::Record,
+    key: &vcf::header::info::Key,
+) -> Option<(i32, i32)> {
+    use vcf::record::info::field::Value;
 
-    match rustler_error {
-        Some(err) => Err(err),
-        None => Ok(result_vector),
+    match record.info().get(key).and_then(|field| field.value()) {
+        Some(Value::IntegerArray(values)) => match values[..] {
+            [Some(lower), Some(upper)] => Some((lower, upper)),
+            _ => None,
+        },
+        _ => None,
     }
 }
 
+/// Reads samtools' `DP4` as its ref-forward, ref-reverse, alt-forward and alt-reverse depths,
+/// whether or not the header declares it, or `None` unless it is four integers.
+fn strand_read_depths(record: &vcf::record::Record) -> Option<[i32; 4]> {
+    use std::convert::TryFrom;
+    use vcf::record::info::field::Value;
+
+    let key: vcf::header::info::Key = "DP4".parse().ok()?;
+    match record.info().get(&key)?.value()? {
+        Value::IntegerArray(values) => match values[..] {
+            [Some(a), Some(b), Some(c), Some(d)] => Some([a, b, c, d]),
+            _ => None,
+        },
+        Value::String(value) => {
+            let depths: Vec<i32> = value
+                .split(',')
+                .map(str::parse)
+                .collect::<Result<_, _>>()
+                .ok()?;
+            <[i32; 4]>::try_from(depths).ok()
+        }
+        _ => None,
+    }
+}
+
+/// Counts `NS`, `AN` and `AC` from the `GT` calls, or `None` when the record has no calls.
+fn computed_stats(record: &vcf::record::Record) -> Option<(i32, i32, Vec<i32>)> {
+    if !record
+        .format()
+        .contains(&vcf::header::format::Key::Genotype)
+    {
+        return None;
+    }
+
+    let mut samples_with_data = 0;
+    let mut total_alleles = 0;
+    let mut allele_counts = vec![0; record.alternate_bases().len()];
+    for genotype in record.genotypes().iter() {
+        let call = match genotype.genotype() {
+            Some(Ok(call)) => call,
+            _ => continue,
+        };
+        let mut has_data = false;
+        for position in call.iter().filter_map(|allele| allele.position()) {
+            has_data = true;
+            total_alleles += 1;
+            if let Some(count) = position
+                .checked_sub(1)
+                .and_then(|i| allele_counts.get_mut(i))
+            {
+                *count += 1;
+            }
+        }
+        if has_data {
+            samples_with_data += 1;
+        }
+    }
+
+    Some((samples_with_data, total_alleles, allele_counts))
+}
+
+/// Splits a CIGAR string such as `1M1I2M` into `{op, length}` pairs.
+fn parse_cigar(cigar: &str) -> Option<Vec<(String, usize)>> {
+    let mut ops = Vec::new();
+    let mut rest = cigar;
+    while !rest.is_empty() {
+        let digits = rest.find(|c: char| !c.is_ascii_digit())?;
+        let length = rest[..digits].parse().ok()?;
+        let op = rest[digits..].chars().next()?;
+        if !"MIDNSHP=X".contains(op) {
+            return None;
+        }
+        ops.push((op.to_string(), length));
+        rest = &rest[digits + 1..];
+    }
+    Some(ops)
+}
+
+/// A `CIGAR` INFO value as one list of `{op, length}` pairs per alternate allele.
+///
+/// Returns `None` if any entry is not a valid CIGAR, leaving the caller to keep the raw strings.
+fn cigar_to_term<'a>(
+    env: Env<'a>,
+    value: &vcf::record::info::field::Value,
+    options: &ReadOptions,
+) -> Option<Term<'a>> {
+    use vcf::record::info::field::Value;
+
+    let cigars = match value {
+        Value::String(cigar) => vec![Some(cigar.as_str())],
+        Value::StringArray(cigars) => cigars.iter().map(Option::as_deref).collect(),
+        _ => return None,
+    };
+    let mut terms = Vec::with_capacity(cigars.len());
+    for cigar in cigars {
+        terms.push(match cigar {
+            Some(cigar) => parse_cigar(cigar)?.encode(env),
+            None => options.missing.encode(env),
+        });
+    }
+    Some(terms.encode(env))
+}
+
+/// Reads a `MEINFO` (`NAME,START,END,POLARITY`) or `METRANS` (`CHR,START,END,POLARITY`) value as
+/// a `{name, start, end, polarity}` tuple, or `None` unless it is four values with integer bounds.
+fn mobile_element_to_term<'a>(
+    env: Env<'a>,
+    value: &vcf::record::info::field::Value,
+) -> Option<Term<'a>> {
+    use vcf::record::info::field::Value;
+
+    let values = match value {
+        Value::StringArray(values) => values,
+        _ => return None,
+    };
+    match &values[..] {
+        [Some(name), Some(start), Some(end), Some(polarity)] => Some(
+            (
+                name.as_str(),
+                start.parse::<i64>().ok()?,
+                end.parse::<i64>().ok()?,
+                polarity.as_str(),
+            )
+                .encode(env),
+        ),
+        _ => None,
+    }
+}
+
+/// Reads a `CICNADJ`-style value, two bounds per interval, as `{lower, upper}` tuples, or `None`
+/// unless it is a non-empty, even number of integers or floats with none missing.
+fn interval_pairs_to_terms<'a>(
+    env: Env<'a>,
+    value: &vcf::record::info::field::Value,
+) -> Option<Vec<Term<'a>>> {
+    use vcf::record::info::field::Value;
+
+    fn pairs<'a, T: Encoder + Copy>(env: Env<'a>, values: &[Option<T>]) -> Option<Vec<Term<'a>>> {
+        if values.is_empty() || !values.len().is_multiple_of(2) {
+            return None;
+        }
+        values
+            .chunks(2)
+            .map(|bounds| Some((bounds[0]?, bounds[1]?).encode(env)))
+            .collect()
+    }
+
+    match value {
+        Value::IntegerArray(values) => pairs(env, values),
+        Value::FloatArray(values) => pairs(env, values),
+        _ => None,
+    }
+}
+
+/// Splits an INFO value into one term per element; a scalar counts as a single element.
+fn info_value_elements<'a>(
+    env: Env<'a>,
+    value: &vcf::record::info::field::Value,
+    missing: MissingValue,
+) -> Option<Vec<Term<'a>>> {
+    use vcf::record::info::field::Value;
+
+    match value {
+        Value::Flag => None,
+        Value::IntegerArray(values) => Some(
+            values
+                .iter()
+                .map(|&value| missing.or_encode(env, value))
+                .collect(),
+        ),
+        Value::FloatArray(values) => Some(
+            values
+                .iter()
+                .map(|&value| missing.or_encode(env, value))
+                .collect(),
+        ),
+        Value::CharacterArray(values) => Some(
+            values
+                .iter()
+                .map(|value| missing.or_encode(env, value.map(String::from)))
+                .collect(),
+        ),
+        Value::StringArray(values) => Some(
+            values
+                .iter()
+                .map(|value| missing.or_encode(env, value.as_deref()))
+                .collect(),
+        ),
+        scalar => Some(vec![info_value_to_term(env, scalar, missing)]),
+    }
+}
+
+/// Maps each `Number=A` or `Number=R` INFO value to the alleles it describes, e.g.
+/// `%{"AF" => %{"C" => 0.25, "G" => 0.5}}`.
+///
+/// Only keys the header declares with such a Number are included, and only when the value has
+/// exactly one element per allele.
+fn allele_info_to_term<'a>(
+    env: Env<'a>,
+    header: &vcf::Header,
+    record: &vcf::record::Record,
+    options: &ReadOptions,
+) -> Result<Term<'a>, RustlerError> {
+    let alleles: Vec<String> = std::iter::once(record.reference_bases().to_string())
+        .chain(
+            record
+                .alternate_bases()
+                .iter()
+                .map(|allele| allele.to_string()),
+        )
+        .collect();
+
+    let mut pairs = Vec::new();
+    for (key, field) in record.info().as_ref() {
+        if !options.includes_info(key.as_ref()) {
+            continue;
+        }
+        let keyed_alleles = match header.infos().get(key).map(|info| info.number()) {
+            Some(vcf::header::Number::A) => &alleles[1..],
+            Some(vcf::header::Number::R) => &alleles[..],
+            _ => continue,
+        };
+        let elements = match field
+            .value()
+            .and_then(|value| info_value_elements(env, value, options.missing))
+        {
+            Some(elements) if elements.len() == keyed_alleles.len() => elements,
+            _ => continue,
+        };
+        let allele_pairs: Vec<(&str, Term)> = keyed_alleles
+            .iter()
+            .map(String::as_str)
+            .zip(elements)
+            .collect();
+        pairs.push((key.as_ref(), Term::map_from_pairs(env, &allele_pairs)?));
+    }
+
+    Term::map_from_pairs(env, &pairs)
+}
+
+fn reserved_info_to_term<'a>(
+    env: Env<'a>,
+    record: &vcf::record::Record,
+    options: &ReadOptions,
+) -> Result<Term<'a>, RustlerError> {
+    let mut pairs = Vec::new();
+    for (key, field) in record.info().as_ref() {
+        if !options.includes_info(key.as_ref()) {
+            continue;
+        }
+        if let Some(atom) = reserved_info_atom(key) {
+            let value = match (key, field.value()) {
+                (vcf::header::info::Key::Cigar, Some(value)) => cigar_to_term(env, value, options)
+                    .unwrap_or_else(|| info_value_to_term(env, value, options.missing)),
+                (
+                    vcf::header::info::Key::MobileElementInfo
+                    | vcf::header::info::Key::MobileElementTransductionInfo,
+                    Some(value),
+                ) => mobile_element_to_term(env, value)
+                    .unwrap_or_else(|| info_value_to_term(env, value, options.missing)),
+                (vcf::header::info::Key::CopyNumberConfidenceIntervals, Some(value)) => {
+                    match interval_pairs_to_terms(env, value).as_deref() {
+                        Some(&[interval]) => interval,
+                        _ => info_value_to_term(env, value, options.missing),
+                    }
+                }
+                (vcf::header::info::Key::AdjacentCopyNumberConfidenceIntervals, Some(value)) => {
+                    interval_pairs_to_terms(env, value)
+                        .map(|intervals| intervals.encode(env))
+                        .unwrap_or_else(|| info_value_to_term(env, value, options.missing))
+                }
+                (_key, Some(value)) => info_value_to_term(env, value, options.missing),
+                (_key, None) => options.missing.encode(env),
+            };
+            pairs.push((atom.to_term(env), value));
+        }
+    }
+
+    if options.includes_info("DP4") {
+        if let Some([ref_forward, ref_reverse, alt_forward, alt_reverse]) =
+            strand_read_depths(record)
+        {
+            use vcf::header::info::Key;
+
+            pairs.push((
+                atoms::strand_read_depths().to_term(env),
+                (ref_forward, ref_reverse, alt_forward, alt_reverse).encode(env),
+            ));
+            // Filled in as `[ref, alt]`, the Number=R shape ADF and ADR take when present.
+            let info = record.info();
+            if info.get(&Key::ForwardStrandReadDepths).is_none() {
+                pairs.push((
+                    atoms::forward_strand_read_depths().to_term(env),
+                    vec![ref_forward, alt_forward].encode(env),
+                ));
+            }
+            if info.get(&Key::ReverseStrandReadDepths).is_none() {
+                pairs.push((
+                    atoms::reverse_strand_read_depths().to_term(env),
+                    vec![ref_reverse, alt_reverse].encode(env),
+                ));
+            }
+        }
+    }
+
+    if options.compute_stats || options.compute_allele_frequencies {
+        if let Some((samples_with_data, total_alleles, allele_counts)) = computed_stats(record) {
+            use vcf::header::info::Key;
+
+            let info = record.info();
+            if options.compute_stats && info.get(&Key::SamplesWithDataCount).is_none() {
+                pairs.push((
+                    atoms::samples_with_data_count().to_term(env),
+                    samples_with_data.encode(env),
+                ));
+            }
+            if options.compute_stats && info.get(&Key::TotalAlleleCount).is_none() {
+                pairs.push((
+                    atoms::total_allele_count().to_term(env),
+                    total_alleles.encode(env),
+                ));
+            }
+            if options.compute_stats && info.get(&Key::AlleleCount).is_none() {
+                pairs.push((
+                    atoms::allele_count().to_term(env),
+                    allele_counts.encode(env),
+                ));
+            }
+            if options.compute_allele_frequencies && info.get(&Key::AlleleFrequencies).is_none() {
+                // With no called alleles the frequency is undefined, so it is nil rather than NaN.
+                let allele_frequencies = (total_alleles > 0).then(|| {
+                    allele_counts
+                        .iter()
+                        .map(|&count| count as f32 / total_alleles as f32)
+                        .collect::<Vec<_>>()
+                });
+                pairs.push((
+                    atoms::allele_frequencies().to_term(env),
+                    allele_frequencies.encode(env),
+                ));
+            }
+        }
+    }
+
+    Term::map_from_pairs(env, &pairs)
+}
+
+fn record_filters(record: &vcf::record::Record) -> VcfRecordFilters {
+    match record.filters() {
+        Some(filters) => match filters {
+            vcf::record::filters::Filters::Pass => VcfRecordFilters::Pass,
+            vcf::record::filters::Filters::Fail(filters) => {
+                VcfRecordFilters::Fail(filters.iter().map(|f| f.to_string()).collect())
+            }
+        },
+        None => VcfRecordFilters::None,
+    }
+}
+
+fn record_to_struct<'a>(
+    env: Env<'a>,
+    header: &vcf::Header,
+    record: &vcf::record::Record,
+    line: usize,
+    options: &ReadOptions,
+) -> Result<VcfRecord<'a>, RustlerError> {
+    let chromosome = record.chromosome().to_string();
+    let position = record.position().into();
+    let interval = VcfPosition {
+        chrom: chromosome.clone(),
+        start: position,
+        end: match record.end() {
+            Ok(end) => end.into(),
+            Err(_err) => position + record.reference_bases().len() - 1,
+        },
+    };
+    let ids = record.ids().iter().map(|id| id.to_string()).collect();
+    let reference_bases = record.reference_bases().to_string();
+    let alternate_bases = record
+        .alternate_bases()
+        .iter()
+        .map(|allele| allele.to_string())
+        .collect();
+    let resolved_alternate_bases = record
+        .alternate_bases()
+        .iter()
+        .map(|allele| match allele {
+            vcf::record::alternate_bases::Allele::Symbol(symbol) => {
+                match header.alternative_alleles().get(symbol) {
+                    Some(alt) => AltDescription {
+                        id: symbol.to_string(),
+                        description: alt.description().to_string(),
+                    }
+                    .encode(env),
+                    None => allele.to_string().encode(env),
+                }
+            }
+            _ => allele.to_string().encode(env),
+        })
+        .collect();
+    let alt_kinds = record
+        .alternate_bases()
+        .iter()
+        .map(|allele| alt_kind(record.reference_bases(), allele))
+        .collect();
+    let variant_type = record
+        .alternate_bases()
+        .iter()
+        .map(|allele| allele_variant_type(record.reference_bases(), allele))
+        .collect();
+    let breakends = record
+        .alternate_bases()
+        .iter()
+        .map(|allele| match allele {
+            vcf::record::alternate_bases::Allele::Breakend(breakend) => parse_breakend(breakend),
+            _ => None,
+        })
+        .collect();
+    let quality_score = options
+        .missing
+        .or_encode(env, record.quality_score().map(f32::from));
+    let filters = record_filters(record);
+    let passed = match filters {
+        VcfRecordFilters::None => None,
+        VcfRecordFilters::Pass => Some(true),
+        VcfRecordFilters::Fail(_) => Some(false),
+    };
+    // noodles types each value from its header definition, so a flag is known by its value.
+    let info_pairs: Vec<(String, Term)> = record
+        .info()
+        .as_ref()
+        .iter()
+        .filter(|(key, _field)| options.includes_info(key.as_ref()))
+        .map(|(key, field)| {
+            let value = match field.value() {
+                Some(vcf::record::info::field::Value::Flag) => true.encode(env),
+                _ => field.to_string().encode(env),
+            };
+            (key.to_string(), value)
+        })
+        .collect();
+    let info = if options.ordered_info {
+        info_pairs.encode(env)
+    } else {
+        Term::map_from_pairs(env, &info_pairs)?
+    };
+    let reserved_info = reserved_info_to_term(env, record, options)?;
+    let allele_info = allele_info_to_term(env, header, record, options)?;
+    let format = if record.genotypes().is_empty() {
+        // Sites-only records have neither a FORMAT column nor any sample columns.
+        Vec::new()
+    } else {
+        record
+            .format()
+            .iter()
+            .filter(|key| options.includes_format(key.as_ref()))
+            .map(|key| key.to_string())
+            .collect()
+    };
+    // Every declared sample gets an entry; one whose column is missing from the line maps to nil.
+    let mut genotypes_pairs = Vec::with_capacity(header.sample_names().len());
+    for (i, sample_name) in header.sample_names().iter().enumerate() {
+        let genotype = match record.genotypes().get(i) {
+            Some(genotype) => genotype_to_term(env, genotype, options)?,
+            None => None::<()>.encode(env),
+        };
+        genotypes_pairs.push((sample_name.as_str(), genotype));
+    }
+    let genotypes = Term::map_from_pairs(env, &genotypes_pairs)?;
+    // Ploidy is the GT allele count; a missing (`.`), unparsable or absent GT has none.
+    let ploidy_pairs: Vec<(&str, Option<usize>)> = header
+        .sample_names()
+        .iter()
+        .enumerate()
+        .map(|(i, sample_name)| {
+            let ploidy = match record
+                .genotypes()
+                .get(i)
+                .map(|genotype| genotype.genotype())
+            {
+                Some(Some(Ok(call))) => Some(call.len()),
+                _ => None,
+            };
+            (sample_name.as_str(), ploidy)
+        })
+        .collect();
+    let ploidy = Term::map_from_pairs(env, &ploidy_pairs)?;
+
+    Ok(VcfRecord {
+        line,
+        chromosome,
+        position,
+        interval,
+        ids,
+        reference_bases,
+        alternate_bases,
+        resolved_alternate_bases,
+        alt_kinds,
+        variant_type,
+        breakends,
+        quality_score,
+        filters,
+        passed,
+        info,
+        reserved_info,
+        allele_info,
+        position_confidence_intervals: confidence_interval(
+            record,
+            &vcf::header::info::Key::PositionConfidenceIntervals,
+        ),
+        end_confidence_intervals: confidence_interval(
+            record,
+            &vcf::header::info::Key::EndConfidenceIntervals,
+        ),
+        format,
+        genotypes,
+        ploidy,
+    })
+}
+
+/// Rejects `fileformat` versions newer than the VCF 4.3 rules noodles parses records with.
+fn check_file_format(header: &vcf::Header) -> Result<(), RustlerError> {
+    let file_format = header.file_format();
+    if file_format.major() == 4 && file_format.minor() <= 3 {
+        Ok(())
+    } else {
+        Err(RustlerError::Term(Box::new((
+            atoms::unsupported_file_format(),
+            file_format.major(),
+            file_format.minor(),
+        ))))
+    }
+}
+
+fn open_handle(
+    input: VcfInput,
+    path: Option<String>,
+    options: HandleOptions,
+) -> Result<ResourceArc<VcfHandle>, RustlerError> {
+    let mut vcf_reader = vcf::Reader::new(input);
+    let (header, header_lines) = handle_io_error!(read_raw_header(&mut vcf_reader));
+    match header.parse::<vcf::header::Header>() {
+        Ok(header) => {
+            check_file_format(&header)?;
+            let bgzf = matches!(vcf_reader.get_ref(), VcfInput::Bgzf(_));
+            let compression = match vcf_reader.get_ref() {
+                VcfInput::Bgzf(_) => Compression::Bgzf,
+                VcfInput::Gzip(_) => Compression::Gzip,
+                VcfInput::File(_)
+                | VcfInput::Mmap(_)
+                | VcfInput::Memory(_)
+                | VcfInput::Stdin(_) => Compression::None,
+            };
+            let resource_arc = ResourceArc::new(VcfHandle {
+                header: sync::RwLock::new(header),
+                stream: sync::Mutex::new(vcf_reader),
+                line: AtomicUsize::new(0),
+                line_known: AtomicBool::new(true),
+                header_lines: AtomicUsize::new(0),
+                data_offset: AtomicU64::new(0),
+                path,
+                bgzf,
+                compression,
+                index: sync::Mutex::new(None),
+                missing: options.missing,
+                max_field_len: options.max_field_len,
+            });
+            resource_arc.mark_data_start(&mut lock(&resource_arc.stream), header_lines);
+
+            Ok(resource_arc)
+        }
+        Err(err) => Err(header_error(&header, err)),
+    }
+}
+
+#[rustler::nif]
+fn get_handle(path: String) -> Result<ResourceArc<VcfHandle>, RustlerError> {
+    open_file_handle(path, HandleOptions::default())
+}
+
+#[rustler::nif(name = "get_handle")]
+fn get_handle_with_opts(
+    path: String,
+    opts: Vec<(Atom, Term)>,
+) -> Result<ResourceArc<VcfHandle>, RustlerError> {
+    open_file_handle(path, decode_handle_options(opts)?)
+}
+
+/// Opens `path` with the decoder its magic bytes call for, or the one `compression:` forces.
+///
+/// A forced `compression:` or `format:` the content does not bear out fails with
+/// `{:format_mismatch, forced, detected}`; `:gzip` also reads bgzf, which is valid gzip, just
+/// without seeking. Forcing `format: :bcf` on BCF content fails with `{:unsupported_format, :bcf}`,
+/// since handles only read VCF text.
+fn open_file_handle(
+    path: String,
+    options: HandleOptions,
+) -> Result<ResourceArc<VcfHandle>, RustlerError> {
+    let file_result = File::open(&path);
+    let mut file = handle_io_error!(file_result);
+    let detected = handle_io_error!(detect_compression(&mut file));
+    let compression = match options.compression {
+        None => detected,
+        Some(forced) if forced == detected => forced,
+        Some(forced) if forced == atoms::gzip() && detected == atoms::bgzf() => forced,
+        Some(forced) => return Err(format_mismatch(forced, detected)),
+    };
+
+    let mut input = VcfInput::with_compression(file, compression, options);
+    if let Some(forced) = options.format {
+        let detected = handle_io_error!(input.content_format());
+        if forced != detected {
+            return Err(format_mismatch(forced, detected));
+        }
+        if forced == atoms::bcf() {
+            return Err(RustlerError::Term(Box::new((
+                atoms::unsupported_format(),
+                atoms::bcf(),
+            ))));
+        }
+    }
+    open_handle(input, Some(path), options)
+}
+
+fn format_mismatch(forced: Atom, detected: Atom) -> RustlerError {
+    RustlerError::Term(Box::new((atoms::format_mismatch(), forced, detected)))
+}
+
+/// Opens a local file through a read-only memory map instead of a buffered reader.
+///
+/// The file must not be truncated or rewritten while the handle is alive.
+#[rustler::nif(schedule = "DirtyIo")]
+fn get_handle_mmap(path: String) -> Result<ResourceArc<VcfHandle>, RustlerError> {
+    let file_result = File::open(&path);
+    let file = handle_io_error!(file_result);
+    // SAFETY: the mapping is only ever read, and callers are told not to modify the file.
+    let mmap = handle_io_error!(unsafe { Mmap::map(&file) });
+    open_handle(
+        VcfInput::Mmap(Cursor::new(mmap)),
+        Some(path),
+        HandleOptions::default(),
+    )
+}
+
+/// Opens a handle over an uncompressed VCF held in an Elixir binary, which is copied.
+///
+/// Like a stdin handle it has no path, so calls that would reopen the file read and consume the
+/// handle's own stream instead.
+#[rustler::nif(schedule = "DirtyCpu")]
+fn get_handle_from_binary(data: rustler::Binary) -> Result<ResourceArc<VcfHandle>, RustlerError> {
+    open_handle(
+        VcfInput::Memory(Cursor::new(data.as_slice().to_vec())),
+        None,
+        HandleOptions::default(),
+    )
+}
+
+#[rustler::nif(schedule = "DirtyIo")]
+fn get_handle_from_stdin() -> Result<ResourceArc<VcfHandle>, RustlerError> {
+    open_handle(
+        VcfInput::Stdin(BufReader::new(std::io::stdin())),
+        None,
+        HandleOptions::default(),
+    )
+}
+
+macro_rules! seek_to_start {
+    ($stream:expr) => {
+        match $stream.get_mut().seek_to_start() {
+            Some(result) => handle_io_error!(result),
+            None => return Err(RustlerError::Term(Box::new(atoms::not_seekable()))),
+        }
+    };
+}
+
+#[rustler::nif]
+fn handle_info(env: Env, handle: ResourceArc<VcfHandle>) -> HandleInfo {
+    HandleInfo {
+        source: match &handle.path {
+            Some(path) => path.encode(env),
+            None if matches!(lock(&handle.stream).get_ref(), VcfInput::Memory(_)) => {
+                atoms::binary().encode(env)
+            }
+            None => atoms::stdin().encode(env),
+        },
+        compression: handle.compression.to_atom(),
+        format: atoms::vcf(),
+        index_loaded: lock(&handle.index).is_some(),
+    }
+}
+
+/// Moves `stream` back to the first record, failing with `:not_seekable` for stdin.
+fn rewind_stream(
+    handle: &VcfHandle,
+    stream: &mut vcf::Reader<VcfInput>,
+) -> Result<(), RustlerError> {
+    // In-memory bytes cannot change, so the records still start where they did and the header
+    // need not be read again.
+    if matches!(stream.get_ref(), VcfInput::Mmap(_) | VcfInput::Memory(_)) {
+        let data_offset = handle.data_offset.load(Ordering::Relaxed);
+        if let Some(result) = stream.get_mut().seek_to(data_offset) {
+            handle_io_error!(result);
+        }
+        handle.mark_data_start(stream, handle.header_lines.load(Ordering::Relaxed));
+        return Ok(());
+    }
+    seek_to_start!(stream);
+    let (_raw_header, header_lines) = handle_io_error!(read_raw_header(stream));
+    handle.mark_data_start(stream, header_lines);
+    Ok(())
+}
+
+#[rustler::nif]
+fn rewind(handle: ResourceArc<VcfHandle>) -> Result<Atom, RustlerError> {
+    rewind_stream(&handle, &mut lock(&handle.stream))?;
+    Ok(atoms::ok())
+}
+
+#[rustler::nif]
+fn reload_header(handle: ResourceArc<VcfHandle>) -> Result<Atom, RustlerError> {
+    let mut header = write_lock(&handle.header);
+    let mut stream = lock(&handle.stream);
+    seek_to_start!(stream);
+    let (raw_header, header_lines) = handle_io_error!(read_raw_header(&mut stream));
+    handle.mark_data_start(&mut stream, header_lines);
+    match raw_header.parse::<vcf::header::Header>() {
+        Ok(new_header) => {
+            check_file_format(&new_header)?;
+            *header = new_header;
+            Ok(atoms::ok())
+        }
+        Err(err) => Err(header_error(&raw_header, err)),
+    }
+}
+
+/// Replaces the INFO and FILTER definitions records are parsed against with those of `header`, a
+/// `%Noodlex.Vcf.Header{}`, without moving the reader. FORMAT, contig and sample definitions stay
+/// as read from the file, whatever the struct's `formats` and `contigs` say. The override lasts
+/// until `reload_header`.
+#[rustler::nif]
+fn set_parse_header(
+    handle: ResourceArc<VcfHandle>,
+    header: VcfHeader,
+) -> Result<Atom, RustlerError> {
+    let mut infos = vcf::header::Infos::new();
+    for (id, info) in
+        rustler::types::map::MapIterator::new(header.infos).ok_or(RustlerError::BadArg)?
+    {
+        let info: VcfInfo = info.decode()?;
+        let id = id.atom_to_string()?;
+        let key = id.parse().map_err(|_| RustlerError::BadArg)?;
+        let value = vcf::header::record::value::Map::<vcf::header::record::value::map::Info>::new(
+            key,
+            term_to_number(info.number)?,
+            atom_to_info_type(info.type_)?,
+            info.description,
+        );
+        infos.insert(value.id().clone(), value);
+    }
+    let mut filters = vcf::header::Filters::new();
+    for (_id, filter) in
+        rustler::types::map::MapIterator::new(header.filters).ok_or(RustlerError::BadArg)?
+    {
+        let filter: VcfFilter = filter.decode()?;
+        let value = vcf::header::record::value::Map::<vcf::header::record::value::map::Filter>::new(
+            filter.id.clone(),
+            filter.description,
+        );
+        filters.insert(filter.id, value);
+    }
+    let mut current = write_lock(&handle.header);
+    *current.infos_mut() = infos;
+    *current.filters_mut() = filters;
+    Ok(atoms::ok())
+}
+
+fn term_to_number(term: Term) -> Result<vcf::header::Number, RustlerError> {
+    if let Ok(count) = term.decode::<usize>() {
+        return Ok(vcf::header::Number::Count(count));
+    }
+    let atom: Atom = term.decode()?;
+    if atom == atoms::alternate_alleles() {
+        Ok(vcf::header::Number::A)
+    } else if atom == atoms::reference_and_alternate_alleles() {
+        Ok(vcf::header::Number::R)
+    } else if atom == atoms::genotypes() {
+        Ok(vcf::header::Number::G)
+    } else if atom == atoms::unknown() {
+        Ok(vcf::header::Number::Unknown)
+    } else {
+        Err(RustlerError::BadArg)
+    }
+}
+
+fn atom_to_info_type(atom: Atom) -> Result<vcf::header::info::ty::Type, RustlerError> {
+    if atom == atoms::integer() {
+        Ok(vcf::header::info::ty::Type::Integer)
+    } else if atom == atoms::float() {
+        Ok(vcf::header::info::ty::Type::Float)
+    } else if atom == atoms::flag() {
+        Ok(vcf::header::info::ty::Type::Flag)
+    } else if atom == atoms::character() {
+        Ok(vcf::header::info::ty::Type::Character)
+    } else if atom == atoms::string() {
+        Ok(vcf::header::info::ty::Type::String)
+    } else {
+        Err(RustlerError::BadArg)
+    }
+}
+
+fn number_to_term(env: Env, number: vcf::header::Number) -> Term {
+    match number {
+        vcf::header::Number::Count(count) => count.encode(env),
+        vcf::header::Number::A => atoms::alternate_alleles().to_term(env),
+        vcf::header::Number::R => atoms::reference_and_alternate_alleles().to_term(env),
+        vcf::header::Number::G => atoms::genotypes().to_term(env),
+        vcf::header::Number::Unknown => atoms::unknown().to_term(env),
+    }
+}
+
+/// INFO definitions are keyed by atom, so an ID an atom cannot hold, such as one over 255
+/// characters, fails with `{:info_id_too_long, id}` instead of panicking.
+fn info_id_atom(env: Env, key: &vcf::header::info::Key) -> Result<Atom, RustlerError> {
+    Atom::from_str(env, key.as_ref())
+        .map_err(|_err| RustlerError::Term(Box::new((atoms::info_id_too_long(), key.to_string()))))
+}
+
+fn info_to_struct<'a>(
+    env: Env<'a>,
+    key: &vcf::header::info::Key,
+    value: &vcf::header::record::value::Map<vcf::header::record::value::map::Info>,
+) -> Result<VcfInfo<'a>, RustlerError> {
+    let type_ = match value.ty() {
+        vcf::header::info::ty::Type::Integer => atoms::integer(),
+        vcf::header::info::ty::Type::Float => atoms::float(),
+        vcf::header::info::ty::Type::Flag => atoms::flag(),
+        vcf::header::info::ty::Type::Character => atoms::character(),
+        vcf::header::info::ty::Type::String => atoms::string(),
+    };
+
+    Ok(VcfInfo {
+        id: info_id_atom(env, key)?,
+        number: number_to_term(env, value.number()),
+        type_,
+        description: value.description().to_string(),
+    })
+}
+
+/// Builds a map of ID to fields for structured generic records such as `##PEDIGREE=<ID=...>`.
+fn structured_records_to_term<'a>(
+    env: Env<'a>,
+    header: &vcf::Header,
+    key: &str,
+) -> Result<Term<'a>, RustlerError> {
+    let mut pairs = Vec::new();
+    for value in header.get(&key.into()).unwrap_or_default() {
+        if let vcf::header::record::value::Other::Map(map) = value {
+            let fields: Vec<(&str, &str)> = map
+                .other_fields()
+                .iter()
+                .map(|(tag, value)| (tag.as_ref(), value.as_str()))
+                .collect();
+            pairs.push((map.id().as_str(), Term::map_from_pairs(env, &fields)?));
+        }
+    }
+    Term::map_from_pairs(env, &pairs)
+}
+
+#[rustler::nif]
+fn get_header<'a>(
+    env: Env<'a>,
+    handle: ResourceArc<VcfHandle>,
+) -> Result<VcfHeader<'a>, RustlerError> {
+    header_to_struct(env, &read_lock(&handle.header))
+}
+
+/// Parses just the header of the file at `path`; the file is closed again before returning.
+#[rustler::nif(schedule = "DirtyIo")]
+fn read_header_only(env: Env, path: String) -> Result<VcfHeader, RustlerError> {
+    let file = handle_io_error!(File::open(&path));
+    let input = handle_io_error!(VcfInput::from_file(file, HandleOptions::default()));
+    let header = read_checked_header(&mut vcf::Reader::new(input))?;
+    header_to_struct(env, &header)
+}
+
+/// Checks that `path` opens as a VCF by parsing its header and first record, if it has one, and
+/// closes the file again before returning `{:ok, :valid}`.
+///
+/// Fails with the error `get_handle` or `get_record` would: an I/O error atom such as
+/// `:not_found`, `{:header, line, reason}`, `{:parse, line, reason}` or
+/// `{:invalid_utf8, line, offset}`.
+///
+/// Exposed to Elixir as `valid_vcf?/1`, since NIF names cannot end in `?`.
+#[rustler::nif(schedule = "DirtyIo")]
+fn valid_vcf(path: String) -> Result<(Atom, Atom), RustlerError> {
+    let file = handle_io_error!(File::open(&path));
+    let input = handle_io_error!(VcfInput::from_file(file, HandleOptions::default()));
+    let mut reader = vcf::Reader::new(input);
+    let (raw_header, header_lines) = handle_io_error!(read_raw_header(&mut reader));
+    let header = match raw_header.parse::<vcf::header::Header>() {
+        Ok(header) => header,
+        Err(err) => return Err(header_error(&raw_header, err)),
+    };
+    check_file_format(&header)?;
+
+    if let Some(text) = handle_io_error!(read_record_line(&mut reader)) {
+        let line = header_lines + 1;
+        if let Err(err) = vcf::record::Record::try_from_str(line_text(line, &text)?, &header) {
+            return Err(parse_error(line, err));
+        }
+    }
+    Ok((atoms::ok(), atoms::valid()))
+}
+
+/// Adds `from`'s definitions to `into`, failing with `{:conflicting_definition, kind, id}` when an
+/// ID is already defined differently.
+macro_rules! merge_definitions {
+    ($into:expr, $from:expr, $kind:expr) => {
+        for (id, definition) in $from {
+            match $into.get(id) {
+                Some(existing) if existing != definition => {
+                    return Err(RustlerError::Term(Box::new((
+                        atoms::conflicting_definition(),
+                        $kind,
+                        id.to_string(),
+                    ))))
+                }
+                Some(_existing) => {}
+                None => {
+                    $into.insert(id.clone(), definition.clone());
+                }
+            }
+        }
+    };
+}
+
+/// Combines the headers of several handles into one, as a first step towards merging samples.
+///
+/// INFO, FORMAT and contig definitions are unioned in order of first appearance and sample names
+/// are concatenated; everything else comes from the first header. An ID defined differently by
+/// two headers fails with `{:conflicting_definition, kind, id}`, and a sample name appearing twice
+/// with `{:duplicate_sample, name}`.
+#[rustler::nif]
+fn merge_headers<'a>(
+    env: Env<'a>,
+    handles: Vec<ResourceArc<VcfHandle>>,
+) -> Result<VcfHeader<'a>, RustlerError> {
+    let (first, rest) = handles.split_first().ok_or(RustlerError::BadArg)?;
+    let mut merged = read_lock(&first.header).clone();
+    for handle in rest {
+        let header = read_lock(&handle.header);
+        merge_definitions!(merged.infos_mut(), header.infos(), atoms::info());
+        merge_definitions!(merged.formats_mut(), header.formats(), atoms::format());
+        merge_definitions!(merged.contigs_mut(), header.contigs(), atoms::contig());
+        for sample_name in header.sample_names() {
+            if !merged.sample_names_mut().insert(sample_name.clone()) {
+                return Err(RustlerError::Term(Box::new((
+                    atoms::duplicate_sample(),
+                    sample_name.clone(),
+                ))));
+            }
+        }
+    }
+    header_to_struct(env, &merged)
+}
+
+/// Reads the raw header and returns it with the physical lines it took, first skipping a UTF-8
+/// byte order mark and any blank lines some exporters put before `##fileformat`.
+fn read_raw_header<R: BufRead>(reader: &mut vcf::Reader<R>) -> std::io::Result<(String, usize)> {
+    const BYTE_ORDER_MARK: &[u8] = b"\xEF\xBB\xBF";
+
+    let input = reader.get_mut();
+    if input.fill_buf()?.starts_with(BYTE_ORDER_MARK) {
+        input.consume(BYTE_ORDER_MARK.len());
+    }
+    let mut blank_lines = 0;
+    loop {
+        let buf = input.fill_buf()?;
+        let blanks = buf
+            .iter()
+            .take_while(|&&b| b == b' ' || b == b'\t' || b == b'\r')
+            .count();
+        match buf.get(blanks) {
+            Some(b'\n') => {
+                input.consume(blanks + 1);
+                blank_lines += 1;
+            }
+            // Whitespace running to the end of the buffer may still turn out to be a blank line.
+            None if blanks > 0 => input.consume(blanks),
+            _ => break,
+        }
+    }
+
+    let raw_header = reader.read_header()?;
+    let header_lines = blank_lines + raw_header.lines().count();
+    Ok((raw_header, header_lines))
+}
+
+/// Reads and parses a header, rejecting the same files `get_handle` does.
+fn read_checked_header<R: BufRead>(
+    reader: &mut vcf::Reader<R>,
+) -> Result<vcf::Header, RustlerError> {
+    let (raw_header, _header_lines) = handle_io_error!(read_raw_header(reader));
+    let header = match raw_header.parse::<vcf::header::Header>() {
+        Ok(header) => header,
+        Err(err) => return Err(header_error(&raw_header, err)),
+    };
+    check_file_format(&header)?;
+    Ok(header)
+}
+
+fn header_to_struct<'a>(env: Env<'a>, header: &vcf::Header) -> Result<VcfHeader<'a>, RustlerError> {
+    let fileformat = FileFormat {
+        major: header.file_format().major(),
+        minor: header.file_format().minor(),
+    };
+    let mut infos_vector = Vec::new();
+    for (key, value) in header.infos() {
+        infos_vector.push((info_id_atom(env, key)?, info_to_struct(env, key, value)?));
+    }
+    let mut formats_vector = Vec::new();
+    for (key, value) in header.formats() {
+        let format = VcfFormat {
+            id: key.to_string(),
+            number: number_to_term(env, value.number()),
+            type_: match value.ty() {
+                vcf::header::format::ty::Type::Integer => atoms::integer(),
+                vcf::header::format::ty::Type::Float => atoms::float(),
+                vcf::header::format::ty::Type::Character => atoms::character(),
+                vcf::header::format::ty::Type::String => atoms::string(),
+            },
+            description: value.description().to_string(),
+        };
+        formats_vector.push((key.to_string(), format));
+    }
+    let mut filters_vector = Vec::new();
+    for (key, value) in header.filters() {
+        filters_vector.push((
+            key.to_string(),
+            VcfFilter {
+                id: key.to_string(),
+                description: value.description().to_string(),
+            },
+        ));
+    }
+    let mut other_vector = Vec::new();
+    if let Some(assembly) = header.assembly() {
+        other_vector.push(("assembly".to_string(), vec![assembly.to_string()]));
+    }
+    for (key, values) in header.other_records() {
+        let values: Vec<String> = values.iter().map(|value| value.to_string()).collect();
+        other_vector.push((key.to_string(), values));
+    }
+    let contigs_vector: Vec<(&str, Option<usize>)> = header
+        .contigs()
+        .iter()
+        .map(|(name, contig)| (name.as_ref(), contig.length()))
+        .collect();
+    let pedigrees = structured_records_to_term(env, header, "PEDIGREE")?;
+    let samples_meta = structured_records_to_term(env, header, "SAMPLE")?;
+    match (
+        Term::map_from_pairs(env, &infos_vector),
+        Term::map_from_pairs(env, &formats_vector),
+        Term::map_from_pairs(env, &filters_vector),
+        Term::map_from_pairs(env, &contigs_vector),
+        Term::map_from_pairs(env, &other_vector),
+    ) {
+        (Ok(infos), Ok(formats), Ok(filters), Ok(contigs), Ok(other)) => Ok(VcfHeader {
+            fileformat,
+            infos,
+            formats,
+            filters,
+            contigs,
+            other,
+            pedigrees,
+            samples_meta,
+            sample_names: header.sample_names().iter().cloned().collect(),
+        }),
+        _ => Err(RustlerError::Term(Box::new(atoms::error()))),
+    }
+}
+
+#[rustler::nif]
+fn describe_info_key<'a>(
+    env: Env<'a>,
+    handle: ResourceArc<VcfHandle>,
+    key: String,
+) -> Result<VcfInfo<'a>, RustlerError> {
+    let header = read_lock(&handle.header);
+    let parsed_key = match key.parse::<vcf::header::info::Key>() {
+        Ok(parsed_key) => parsed_key,
+        Err(err) => return Err(RustlerError::Term(Box::new(err.to_string()))),
+    };
+    match header.infos().get(&parsed_key) {
+        Some(value) => info_to_struct(env, &parsed_key, value),
+        None => Err(RustlerError::Term(Box::new(atoms::not_found()))),
+    }
+}
+
+/// The reserved structural-variant INFO keys (VCF 4.3 § 3).
+fn is_sv_info_key(key: &vcf::header::info::Key) -> bool {
+    use vcf::header::info::Key;
+
+    matches!(
+        key,
+        Key::IsImprecise
+            | Key::IsNovel
+            | Key::EndPosition
+            | Key::SvType
+            | Key::SvLengths
+            | Key::PositionConfidenceIntervals
+            | Key::EndConfidenceIntervals
+            | Key::MicrohomologyLengths
+            | Key::MicrohomologySequences
+            | Key::BreakpointIds
+            | Key::MobileElementInfo
+            | Key::MobileElementTransductionInfo
+            | Key::DbvId
+            | Key::DbVarId
+            | Key::DbRipId
+            | Key::MateBreakendIds
+            | Key::PartnerBreakendId
+            | Key::BreakendEventId
+            | Key::BreakendConfidenceIntervals
+            | Key::AdjacentReadDepths
+            | Key::BreakendCopyNumber
+            | Key::AdjacentCopyNumber
+            | Key::CopyNumberConfidenceIntervals
+            | Key::AdjacentCopyNumberConfidenceIntervals
+    )
+}
+
+/// Like the header's `infos`, restricted to reserved structural-variant keys.
+#[rustler::nif]
+fn sv_info_definitions<'a>(
+    env: Env<'a>,
+    handle: ResourceArc<VcfHandle>,
+) -> Result<Term<'a>, RustlerError> {
+    let header = read_lock(&handle.header);
+    let mut infos_vector = Vec::new();
+    for (key, value) in header.infos() {
+        if is_sv_info_key(key) {
+            infos_vector.push((info_id_atom(env, key)?, info_to_struct(env, key, value)?));
+        }
+    }
+    Term::map_from_pairs(env, &infos_vector)
+}
+
+#[rustler::nif]
+fn info_keys(handle: ResourceArc<VcfHandle>) -> Vec<String> {
+    let header = read_lock(&handle.header);
+    header.infos().keys().map(|key| key.to_string()).collect()
+}
+
+#[rustler::nif]
+fn format_keys(handle: ResourceArc<VcfHandle>) -> Vec<String> {
+    let header = read_lock(&handle.header);
+    header.formats().keys().map(|key| key.to_string()).collect()
+}
+
+#[rustler::nif]
+fn get_record<'a>(
+    env: Env<'a>,
+    handle: ResourceArc<VcfHandle>,
+) -> Result<VcfRecord<'a>, RustlerError> {
+    match read_next_record(env, &handle, &handle.read_options())? {
+        Some(record) => Ok(record),
+        None => Err(RustlerError::Term(Box::new(atoms::end_of_file()))),
+    }
+}
+
+/// Like `get_record`, with read options.
+///
+/// `on_error: :skip` moves past bad lines to the next record that parses. `on_error: :collect`
+/// does the same but returns `{record, errors}`, where `record` is `nil` at end of file.
+#[rustler::nif(name = "get_record")]
+fn get_record_with_opts<'a>(
+    env: Env<'a>,
+    handle: ResourceArc<VcfHandle>,
+    opts: Vec<(Atom, Term<'a>)>,
+) -> Result<Term<'a>, RustlerError> {
+    let options = decode_read_options(&handle, opts)?;
+    if options.on_error == OnError::Stop {
+        return match read_next_record(env, &handle, &options)? {
+            Some(record) => Ok(record.encode(env)),
+            None => Err(RustlerError::Term(Box::new(atoms::end_of_file()))),
+        };
+    }
+
+    let (mut records, errors) = read_records_lenient(env, &handle, 1, &options)?;
+    match (options.on_error, records.pop()) {
+        (OnError::Collect, record) => Ok((record, errors).encode(env)),
+        (_, Some(record)) => Ok(record.encode(env)),
+        (_, None) => Err(RustlerError::Term(Box::new(atoms::end_of_file()))),
+    }
+}
+
+/// Like `get_record`, but end of file is a plain `:end_of_file` and records come as
+/// `{:ok, record}`.
+#[rustler::nif]
+fn next_record<'a>(env: Env<'a>, handle: ResourceArc<VcfHandle>) -> Result<Term<'a>, RustlerError> {
+    match read_next_record(env, &handle, &handle.read_options())? {
+        Some(record) => Ok((atoms::ok(), record).encode(env)),
+        None => Ok(atoms::end_of_file().encode(env)),
+    }
+}
+
+/// Returns the next record line verbatim, without its line ending, or `:end_of_file`.
+#[rustler::nif]
+fn get_raw_record<'a>(
+    env: Env<'a>,
+    handle: ResourceArc<VcfHandle>,
+) -> Result<Term<'a>, RustlerError> {
+    match read_record_lines(&handle, Some(1))?.pop() {
+        Some((line, text)) => Ok(line_text(line, &text)?.encode(env)),
+        None => Ok(atoms::end_of_file().encode(env)),
+    }
+}
+
+/// Returns up to `count` record lines verbatim, without their line endings or any parsing.
+#[rustler::nif]
+fn read_raw_records(
+    handle: ResourceArc<VcfHandle>,
+    count: usize,
+) -> Result<Vec<String>, RustlerError> {
+    let lines = read_record_lines(&handle, Some(count))?;
+    lines
+        .iter()
+        .map(|(line, text)| line_text(*line, text).map(str::to_string))
+        .collect()
+}
+
+/// Returns the file's first record, or `nil` when it has none, and leaves the handle rewound so
+/// the next read returns it again. Fails with `{:error, :not_seekable}` for stdin.
+#[rustler::nif(schedule = "DirtyIo")]
+fn peek_first_record<'a>(
+    env: Env<'a>,
+    handle: ResourceArc<VcfHandle>,
+) -> Result<Option<VcfRecord<'a>>, RustlerError> {
+    rewind_stream(&handle, &mut lock(&handle.stream))?;
+    let record = read_next_record(env, &handle, &handle.read_options());
+    rewind_stream(&handle, &mut lock(&handle.stream))?;
+    record
+}
+
+/// Reads and converts the next record, or `None` at end of file.
+fn read_next_record<'a>(
+    env: Env<'a>,
+    handle: &VcfHandle,
+    options: &ReadOptions,
+) -> Result<Option<VcfRecord<'a>>, RustlerError> {
+    let header = read_lock(&handle.header);
+    let (line, text) = match read_record_lines(handle, Some(1))?.pop() {
+        Some(numbered_line) => numbered_line,
+        // An empty read is end of file, including a file whose header has no records after it.
+        None => return Ok(None),
+    };
+    let buf = line_text(line, &text)?;
+    options.check_field_lengths(&header, line, buf)?;
+    match vcf::record::Record::try_from_str(buf, &header) {
+        Ok(record) => record_to_struct(env, &header, &record, line, options).map(Some),
+        Err(err) => Err(parse_error(line, err)),
+    }
+}
+
+/// Reads the next record, as `get_record` would, and returns only `sample_name`'s genotype map,
+/// or an empty map when the line has no column for the sample.
+///
+/// The record is consumed, so the handle moves on to the one after it; a sample missing from the
+/// header fails with `{:not_found, sample_name}` before anything is read.
+#[rustler::nif]
+fn get_sample_genotype<'a>(
+    env: Env<'a>,
+    handle: ResourceArc<VcfHandle>,
+    sample_name: String,
+) -> Result<Term<'a>, RustlerError> {
+    let header = read_lock(&handle.header);
+    let sample_index = match header.sample_names().get_index_of(&sample_name) {
+        Some(sample_index) => sample_index,
+        None => {
+            return Err(RustlerError::Term(Box::new((
+                atoms::not_found(),
+                sample_name,
+            ))))
+        }
+    };
+    let (line, text) = match read_record_lines(&handle, Some(1))?.pop() {
+        Some(numbered_line) => numbered_line,
+        None => return Err(RustlerError::Term(Box::new(atoms::end_of_file()))),
+    };
+    let options = handle.read_options();
+    let buf = line_text(line, &text)?;
+    options.check_field_lengths(&header, line, buf)?;
+    match vcf::record::Record::try_from_str(buf, &header) {
+        Ok(record) => match record.genotypes().get(sample_index) {
+            Some(genotype) => genotype_to_term(env, genotype, &options),
+            None => Ok(Term::map_new(env)),
+        },
+        Err(err) => Err(parse_error(line, err)),
+    }
+}
+
+/// Whether the stream has nothing left to read, found by peeking at its buffer.
+///
+/// Exposed to Elixir as `at_eof?/1`, since NIF names cannot end in `?`.
+#[rustler::nif]
+fn at_eof(handle: ResourceArc<VcfHandle>) -> Result<bool, RustlerError> {
+    let mut stream = lock(&handle.stream);
+    Ok(handle_io_error!(stream.get_mut().fill_buf()).is_empty())
+}
+
+/// Estimates how many records are left from the bytes still unread and the average bytes per
+/// record read so far, for progress reporting where an exact count would need a full scan.
+///
+/// Returns `nil` until a record has been read or after an index seek loses the count. Bgzf
+/// offsets only move a block at a time, so estimates there are coarser.
+#[rustler::nif]
+fn estimate_remaining(handle: ResourceArc<VcfHandle>) -> Result<Option<u64>, RustlerError> {
+    let mut stream = lock(&handle.stream);
+    let (offset, len) = match (stream.get_mut().byte_offset(), stream.get_ref().byte_len()) {
+        (Some(offset), Some(len)) => (handle_io_error!(offset), handle_io_error!(len)),
+        _ => return Err(RustlerError::Term(Box::new(atoms::not_seekable()))),
+    };
+
+    let records_read = handle
+        .line
+        .load(Ordering::Relaxed)
+        .saturating_sub(handle.header_lines.load(Ordering::Relaxed));
+    let bytes_read = offset.saturating_sub(handle.data_offset.load(Ordering::Relaxed));
+    if !handle.line_known.load(Ordering::Relaxed) || records_read == 0 || bytes_read == 0 {
+        return Ok(None);
+    }
+    let remaining_bytes = len.saturating_sub(offset) as f64;
+    Ok(Some(
+        (remaining_bytes * records_read as f64 / bytes_read as f64).round() as u64,
+    ))
+}
+
+/// Reads past up to `count` records without parsing them and returns how many there were.
+///
+/// This also steps over a line that `get_record` rejects as invalid UTF-8.
+fn skip_record_lines(
+    handle: &VcfHandle,
+    stream: &mut vcf::Reader<VcfInput>,
+    count: usize,
+) -> Result<usize, IoError> {
+    let mut skipped = 0;
+
+    while skipped < count {
+        if read_record_line(stream)?.is_none() {
+            break;
+        }
+        skipped += 1;
+    }
+    handle.line.fetch_add(skipped, Ordering::Relaxed);
+
+    Ok(skipped)
+}
+
+#[rustler::nif]
+fn skip_records(handle: ResourceArc<VcfHandle>, count: usize) -> Result<usize, RustlerError> {
+    let mut stream = lock(&handle.stream);
+    Ok(handle_io_error!(skip_record_lines(
+        &handle,
+        &mut stream,
+        count
+    )))
+}
+
+/// Returns the records with zero-based indices in `start_index..end_index`, fewer if the input
+/// ends first.
+///
+/// The handle is rewound and skipped forward to `start_index`, so it is left just past the
+/// range; the stream stays locked throughout, so no other read can land in between. Input that
+/// cannot be rewound fails with `{:error, :not_seekable}`.
+#[rustler::nif(schedule = "DirtyIo")]
+fn get_records_range<'a>(
+    env: Env<'a>,
+    handle: ResourceArc<VcfHandle>,
+    start_index: usize,
+    end_index: usize,
+) -> Result<Vec<VcfRecord<'a>>, RustlerError> {
+    let header = read_lock(&handle.header);
+    let mut stream = lock(&handle.stream);
+    rewind_stream(&handle, &mut stream)?;
+    handle_io_error!(skip_record_lines(&handle, &mut stream, start_index));
+    let count = end_index.saturating_sub(start_index);
+    let lines = read_record_lines_where(&handle, &mut stream, Some(count), |_buf| true)?;
+    drop(stream);
+
+    parse_record_lines(env, &header, &lines, &handle.read_options())
+}
+
+/// Where a record line stops being valid UTF-8: the 0-based byte in the line at which the bad
+/// sequence starts.
+struct InvalidUtf8 {
+    valid_up_to: usize,
+}
+
+/// A record line's text, or where it stops being valid UTF-8.
+type RecordText = Result<String, InvalidUtf8>;
+
+/// Reads the next record line without its line ending, or `None` once the records end, at the end
+/// of the input or at a blank line.
+///
+/// Every reader of record lines goes through here so that they agree on where the records end.
+/// The line is read as bytes rather than through `read_record`, whose `String` buffer would turn
+/// bad UTF-8 into an opaque I/O error for the whole read instead of a problem with one line.
+fn read_record_line<R: BufRead>(
+    reader: &mut vcf::Reader<R>,
+) -> std::io::Result<Option<RecordText>> {
+    let mut bytes = Vec::new();
+    reader.get_mut().read_until(b'\n', &mut bytes)?;
+    if bytes.ends_with(b"\n") {
+        bytes.pop();
+    }
+    // Also covers the last line of a CRLF file without a final newline.
+    if bytes.ends_with(b"\r") {
+        bytes.pop();
+    }
+    if bytes.is_empty() {
+        return Ok(None);
+    }
+    Ok(Some(String::from_utf8(bytes).map_err(|err| InvalidUtf8 {
+        valid_up_to: err.utf8_error().valid_up_to(),
+    })))
+}
+
+/// The text of the record line at `line`, or `{:invalid_utf8, line, offset}` if it has none.
+fn line_text(line: usize, text: &RecordText) -> Result<&str, RustlerError> {
+    match text {
+        Ok(buf) => Ok(buf),
+        Err(invalid) => Err(RustlerError::Term(Box::new((
+            atoms::invalid_utf8(),
+            line,
+            invalid.valid_up_to,
+        )))),
+    }
+}
+
+/// Reads up to `count` raw record lines, or every remaining line when `count` is `None`.
+///
+/// Each line is paired with its physical line number in the input. A line that is not valid
+/// UTF-8 is read past like any other, so whoever uses the lines decides what to do about it;
+/// failing reads report it as `{:invalid_utf8, line, offset}`.
+fn read_record_lines(
+    handle: &VcfHandle,
+    count: Option<usize>,
+) -> Result<Vec<(usize, RecordText)>, RustlerError> {
+    read_record_lines_where(handle, &mut lock(&handle.stream), count, |_buf| true)
+}
+
+/// Like `read_record_lines`, but from a `stream` the caller holds, and lines `keep` turns down
+/// are read past without counting towards `count`. Lines that are not valid UTF-8 are always
+/// kept so that they are reported.
+fn read_record_lines_where(
+    handle: &VcfHandle,
+    stream: &mut vcf::Reader<VcfInput>,
+    count: Option<usize>,
+    mut keep: impl FnMut(&str) -> bool,
+) -> Result<Vec<(usize, RecordText)>, RustlerError> {
+    let mut lines = Vec::with_capacity(count.unwrap_or_default());
+
+    while count.is_none_or(|count| lines.len() < count) {
+        let text = match handle_io_error!(read_record_line(stream)) {
+            Some(text) => text,
+            None => break,
+        };
+        let line = handle.next_line();
+        let kept = match &text {
+            Ok(buf) => keep(buf),
+            Err(_invalid) => true,
+        };
+        if kept {
+            lines.push((line, text));
+        }
+    }
+
+    Ok(lines)
+}
+
+fn parse_record_lines<'a>(
+    env: Env<'a>,
+    header: &vcf::Header,
+    lines: &[(usize, RecordText)],
+    options: &ReadOptions,
+) -> Result<Vec<VcfRecord<'a>>, RustlerError> {
+    let mut texts = Vec::with_capacity(lines.len());
+    for (line, text) in lines {
+        let buf = line_text(*line, text)?;
+        options.check_field_lengths(header, *line, buf)?;
+        texts.push((*line, buf));
+    }
+    // Parsing is independent per line, so only term construction has to happen on this thread.
+    let parsed_records: Vec<_> = texts
+        .par_iter()
+        .map(|(_line, buf)| vcf::record::Record::try_from_str(buf, header))
+        .collect();
+
+    let mut result_vector = Vec::with_capacity(parsed_records.len());
+    for ((line, _buf), parsed_record) in texts.iter().zip(parsed_records) {
+        match parsed_record {
+            Ok(record) => {
+                result_vector.push(record_to_struct(env, header, &record, *line, options)?)
+            }
+            Err(err) => return Err(parse_error(*line, err)),
+        }
+    }
+
+    Ok(result_vector)
+}
+
+#[rustler::nif]
+fn get_records<'a>(
+    env: Env<'a>,
+    handle: ResourceArc<VcfHandle>,
+    count: usize,
+) -> Result<Vec<VcfRecord<'a>>, RustlerError> {
+    let header = read_lock(&handle.header);
+    let lines = read_record_lines(&handle, Some(count))?;
+
+    parse_record_lines(env, &header, &lines, &handle.read_options())
+}
+
+/// Like `get_records`, but `info:` and `format:` allowlists limit which keys are extracted.
+///
+/// With `on_error: :skip` or `:collect`, bad lines do not count towards `count`, as in
+/// `get_records_lenient`; `:collect` returns `{records, errors}` just like it does.
+#[rustler::nif(name = "get_records")]
+fn get_records_with_opts<'a>(
+    env: Env<'a>,
+    handle: ResourceArc<VcfHandle>,
+    count: usize,
+    opts: Vec<(Atom, Term<'a>)>,
+) -> Result<Term<'a>, RustlerError> {
+    let options = decode_read_options(&handle, opts)?;
+    match options.on_error {
+        OnError::Stop => {
+            let header = read_lock(&handle.header);
+            let lines = read_record_lines(&handle, Some(count))?;
+            Ok(parse_record_lines(env, &header, &lines, &options)?.encode(env))
+        }
+        OnError::Skip => Ok(read_records_lenient(env, &handle, count, &options)?
+            .0
+            .encode(env)),
+        OnError::Collect => Ok(read_records_lenient(env, &handle, count, &options)?.encode(env)),
+    }
+}
+
+/// Reads the next batch of up to `batch_size` records and returns it as `{records, cursor}`,
+/// for Elixir to pull batches in a loop and filter them itself.
+///
+/// The cursor is the handle while more records may follow and `nil` once a batch comes up short,
+/// so the shape fits `Stream.unfold/2`. A `batch_size` of 0 raises `ArgumentError`.
+#[rustler::nif]
+fn get_records_with_cursor<'a>(
+    env: Env<'a>,
+    handle: ResourceArc<VcfHandle>,
+    batch_size: usize,
+) -> Result<(Vec<VcfRecord<'a>>, Option<ResourceArc<VcfHandle>>), RustlerError> {
+    if batch_size == 0 {
+        return Err(RustlerError::BadArg);
+    }
+    let header = read_lock(&handle.header);
+    let lines = read_record_lines(&handle, Some(batch_size))?;
+    let records = parse_record_lines(env, &header, &lines, &handle.read_options())?;
+    drop(header);
+
+    let cursor = match lines.len() < batch_size {
+        true => None,
+        false => Some(handle),
+    };
+    Ok((records, cursor))
+}
+
+/// Reads up to `count` records with a QUAL of at least `min_qual`, skipping the others before
+/// they are parsed. A missing QUAL fails the threshold.
+#[rustler::nif(schedule = "DirtyIo")]
+fn get_records_min_qual<'a>(
+    env: Env<'a>,
+    handle: ResourceArc<VcfHandle>,
+    min_qual: Term<'a>,
+    count: usize,
+) -> Result<Vec<VcfRecord<'a>>, RustlerError> {
+    read_records_min_qual(env, &handle, min_qual, count, false)
+}
+
+/// `get_records_min_qual/3` with options; `keep_missing_qual: true` lets records with a missing
+/// QUAL through.
+#[rustler::nif(name = "get_records_min_qual", schedule = "DirtyIo")]
+fn get_records_min_qual_with_opts<'a>(
+    env: Env<'a>,
+    handle: ResourceArc<VcfHandle>,
+    min_qual: Term<'a>,
+    count: usize,
+    opts: Vec<(Atom, Term<'a>)>,
+) -> Result<Vec<VcfRecord<'a>>, RustlerError> {
+    let mut keep_missing_qual = false;
+    for (key, value) in opts {
+        if key == atoms::keep_missing_qual() {
+            keep_missing_qual = value.decode()?;
+        } else {
+            return Err(RustlerError::BadArg);
+        }
+    }
+    read_records_min_qual(env, &handle, min_qual, count, keep_missing_qual)
+}
+
+fn read_records_min_qual<'a>(
+    env: Env<'a>,
+    handle: &VcfHandle,
+    min_qual: Term<'a>,
+    count: usize,
+    keep_missing_qual: bool,
+) -> Result<Vec<VcfRecord<'a>>, RustlerError> {
+    // Elixir callers write both `30` and `30.0`.
+    let min_qual = match min_qual.decode::<f64>() {
+        Ok(min_qual) => min_qual,
+        Err(_err) => min_qual.decode::<i64>()? as f64,
+    };
+    let header = read_lock(&handle.header);
+    let lines = read_record_lines_where(handle, &mut lock(&handle.stream), Some(count), |buf| {
+        match buf.split('\t').nth(5) {
+            Some(".") => keep_missing_qual,
+            Some(quality_score) => match quality_score.parse::<f64>() {
+                Ok(quality_score) => quality_score >= min_qual,
+                // An unreadable QUAL is kept so that parsing reports it with its line.
+                Err(_err) => true,
+            },
+            None => true,
+        }
+    })?;
+
+    parse_record_lines(env, &header, &lines, &handle.read_options())
+}
+
+/// Telomeric records sit at position 0, which the index clamps to the first base.
+fn index_position(position: vcf::record::Position) -> noodles_core::Position {
+    noodles_core::Position::new(usize::from(position)).unwrap_or(noodles_core::Position::MIN)
+}
+
+fn index_path(path: &str) -> String {
+    format!("{}.tbi", path)
+}
+
+/// Builds a tabix index for a bgzipped VCF, writes it to `<path>.tbi` and returns the record count.
+#[rustler::nif(name = "index", schedule = "DirtyIo")]
+fn build_index(path: String) -> Result<usize, RustlerError> {
+    let file = handle_io_error!(File::open(&path));
+    if !matches!(
+        handle_io_error!(VcfInput::from_file(file, HandleOptions::default())),
+        VcfInput::Bgzf(_)
+    ) {
+        return Err(RustlerError::Term(Box::new(atoms::not_bgzf())));
+    }
+    let file = handle_io_error!(File::open(&path));
+    let mut reader = vcf::Reader::new(bgzf::Reader::new(file));
+    let (raw_header, header_lines) = handle_io_error!(read_raw_header(&mut reader));
+    let header = match raw_header.parse::<vcf::header::Header>() {
+        Ok(header) => header,
+        Err(err) => return Err(header_error(&raw_header, err)),
+    };
+    check_file_format(&header)?;
+
+    let mut indexer = tabix::Index::indexer();
+    indexer.set_header(tabix::index::header::Builder::vcf().build());
+    let mut line = header_lines;
+    let mut count = 0;
+    let mut start_position = reader.virtual_position();
+    while let Some(text) = handle_io_error!(read_record_line(&mut reader)) {
+        line += 1;
+        let end_position = reader.virtual_position();
+        let record = match vcf::record::Record::try_from_str(line_text(line, &text)?, &header) {
+            Ok(record) => record,
+            Err(err) => return Err(parse_error(line, err)),
+        };
+        let end = match record.end() {
+            Ok(end) => end,
+            Err(err) => return Err(parse_error(line, err)),
+        };
+        indexer.add_record(
+            &record.chromosome().to_string(),
+            index_position(record.position()),
+            index_position(end),
+            Chunk::new(start_position, end_position),
+        );
+        start_position = end_position;
+        count += 1;
+    }
+
+    handle_io_error!(tabix::write(index_path(&path), &indexer.build()));
+    Ok(count)
+}
+
+/// Parses `contig`, `contig:start` or `contig:start-end` into a region.
+///
+/// Contig names may themselves contain colons (`HLA-A*01:01`), so the interval is split off at
+/// the last colon, and a string that names a declared contig as a whole is taken as that contig.
+fn parse_region(region: &str, header: &vcf::Header) -> Result<noodles_core::Region, String> {
+    use noodles_core::region::Interval;
+
+    if header.contigs().contains_key(region) {
+        return Ok(noodles_core::Region::new(region, ..));
+    }
+    let (name, interval) = match region.rsplit_once(':') {
+        Some((name, suffix)) => {
+            let interval: Interval = suffix
+                .parse()
+                .map_err(|err| format!("{} in region {:?}", err, region))?;
+            (name, interval)
+        }
+        None => (region, Interval::from(..)),
+    };
+    if name.is_empty() {
+        return Err(format!("missing contig name in region {:?}", region));
+    }
+    if let (Some(start), Some(end)) = (interval.start(), interval.end()) {
+        if start > end {
+            return Err(format!("start is past end in region {:?}", region));
+        }
+    }
+    Ok(noodles_core::Region::new(name, interval))
+}
+
+/// Calls `f` with each record overlapping `region` (e.g. `chr1:100-200`), found through the
+/// file's tabix index.
+///
+/// A region that does not parse fails with `{:invalid_region, reason}`.
+///
+/// The query reads through its own reader, so the handle's sequential position is untouched
+/// and concurrent queries on one handle only share read access to the header and index.
+fn for_each_in_region(
+    handle: &VcfHandle,
+    header: &vcf::Header,
+    region: &str,
+    f: impl FnMut(&vcf::record::Record) -> Result<(), RustlerError>,
+) -> Result<(), RustlerError> {
+    let path = match (&handle.path, handle.bgzf) {
+        (Some(path), true) => path,
+        _ => return Err(RustlerError::Term(Box::new(atoms::not_bgzf()))),
+    };
+    let region = match parse_region(region, header) {
+        Ok(region) => region,
+        Err(reason) => {
+            return Err(RustlerError::Term(Box::new((
+                atoms::invalid_region(),
+                reason,
+            ))))
+        }
+    };
+
+    handle_io_error!(read_region(handle, path, header, &region, f))
+}
+
+/// The I/O behind `for_each_in_region`, with `f`'s own error passed back in the inner result.
+fn read_region<E>(
+    handle: &VcfHandle,
+    path: &str,
+    header: &vcf::Header,
+    region: &noodles_core::Region,
+    mut f: impl FnMut(&vcf::record::Record) -> Result<(), E>,
+) -> std::io::Result<Result<(), E>> {
+    let index = handle.tabix_index(path)?;
+    if !index
+        .header()
+        .reference_sequence_names()
+        .contains(region.name())
+    {
+        return Ok(Ok(()));
+    }
+
+    let file = File::open(path)?;
+    let mut reader = vcf::Reader::new(bgzf::Reader::new(file));
+    for result in reader.query(header, &index, region)? {
+        if let Err(err) = f(&result?) {
+            return Ok(Err(err));
+        }
+    }
+    Ok(Ok(()))
+}
+
+/// Returns the records overlapping `region` (e.g. `chr1:100-200`) using the file's tabix index.
+///
+/// See `for_each_in_region` for how the region is read. Records found this way have no known
+/// physical line and report `line` as 0.
+#[rustler::nif(schedule = "DirtyIo")]
+fn query<'a>(
+    env: Env<'a>,
+    handle: ResourceArc<VcfHandle>,
+    region: String,
+) -> Result<Vec<VcfRecord<'a>>, RustlerError> {
+    let header = read_lock(&handle.header);
+    let options = handle.read_options();
+    let mut records = Vec::new();
+    for_each_in_region(&handle, &header, &region, |record| {
+        if options.max_field_len.is_some() {
+            options.check_field_lengths(&header, 0, &record.to_string())?;
+        }
+        records.push(record_to_struct(env, &header, record, 0, &options)?);
+        Ok(())
+    })?;
+    Ok(records)
+}
+
+/// Summarizes the records overlapping `region` without building a record struct for each.
+///
+/// Takes the same regions as `query` and fails the same way.
+#[rustler::nif(schedule = "DirtyIo")]
+fn region_summary<'a>(
+    env: Env<'a>,
+    handle: ResourceArc<VcfHandle>,
+    region: String,
+) -> Result<RegionSummary<'a>, RustlerError> {
+    let header = read_lock(&handle.header);
+    let mut record_count = 0;
+    let mut variant_types: Vec<(Atom, u64)> = Vec::new();
+    let (mut quality_sum, mut quality_count) = (0.0, 0);
+    for_each_in_region(&handle, &header, &region, |record| {
+        record_count += 1;
+        for allele in record.alternate_bases().iter() {
+            let variant_type = allele_variant_type(record.reference_bases(), allele);
+            match variant_types
+                .iter_mut()
+                .find(|(seen, _count)| *seen == variant_type)
+            {
+                Some((_seen, count)) => *count += 1,
+                None => variant_types.push((variant_type, 1)),
+            }
+        }
+        if let Some(quality_score) = record.quality_score() {
+            quality_sum += f64::from(f32::from(quality_score));
+            quality_count += 1;
+        }
+        Ok(())
+    })?;
+
+    Ok(RegionSummary {
+        record_count,
+        variant_types: Term::map_from_pairs(env, &variant_types)?,
+        mean_quality_score: match quality_count {
+            0 => None,
+            n => Some(quality_sum / f64::from(n)),
+        },
+    })
+}
+
+/// Sums the per-contig record counts a tabix index keeps in its metadata pseudo-bins.
+///
+/// Returns `None` if any contig lacks them, as indexes from some older writers do.
+fn indexed_record_count(index: &tabix::Index) -> Option<u64> {
+    use noodles_csi::{binning_index::ReferenceSequenceExt, BinningIndex};
+
+    index
+        .reference_sequences()
+        .iter()
+        .map(|reference_sequence| {
+            reference_sequence
+                .metadata()
+                .map(|metadata| metadata.mapped_record_count() + metadata.unmapped_record_count())
+        })
+        .sum::<Option<u64>>()
+        .map(|count| count + index.unplaced_unmapped_record_count().unwrap_or(0))
+}
+
+/// Per-contig record counts from the metadata pseudo-bins, in index order.
+fn index_record_counts<I: noodles_csi::BinningIndex>(index: &I) -> Vec<Option<u64>> {
+    use noodles_csi::binning_index::ReferenceSequenceExt;
+
+    index
+        .reference_sequences()
+        .iter()
+        .map(|reference_sequence| {
+            reference_sequence
+                .metadata()
+                .map(|metadata| metadata.mapped_record_count() + metadata.unmapped_record_count())
+        })
+        .collect()
+}
+
+/// Tabix settings as CSI keeps them in its auxiliary data: format, the contig, start and end
+/// columns, the comment character and the skipped line count as `i32`s, then the contig names.
+struct CsiAux {
+    format: i32,
+    columns: [i32; 3],
+    meta: i32,
+    skip: i32,
+    names: Vec<String>,
+}
+
+fn parse_csi_aux(aux: &[u8]) -> Option<CsiAux> {
+    use std::convert::TryFrom;
+
+    let field = |i: usize| -> Option<i32> {
+        let bytes = aux.get(i * 4..i * 4 + 4)?;
+        Some(i32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    };
+    let names_len = usize::try_from(field(6)?).ok()?;
+    let names = aux.get(28..28 + names_len)?;
+    Some(CsiAux {
+        format: field(0)?,
+        columns: [field(1)?, field(2)?, field(3)?],
+        meta: field(4)?,
+        skip: field(5)?,
+        names: names
+            .split(|&b| b == 0)
+            .filter(|name| !name.is_empty())
+            .map(|name| String::from_utf8_lossy(name).into_owned())
+            .collect(),
+    })
+}
+
+/// Maps a tabix format code (the low 16 bits) to `:generic`, `:sam` or `:vcf`.
+fn index_format_atom(format: i32) -> Option<Atom> {
+    match format & 0xffff {
+        0 => Some(atoms::generic()),
+        1 => Some(atoms::sam()),
+        2 => Some(atoms::vcf()),
+        _ => None,
+    }
+}
+
+/// Reads the `<path>.tbi` or, failing that, `<path>.csi` index of a VCF and describes it.
+///
+/// Only the index is read, so the data file itself need not exist. Fails with
+/// `{:error, :not_found}` when neither index does.
+#[rustler::nif(schedule = "DirtyIo")]
+fn read_index<'a>(env: Env<'a>, path: String) -> Result<IndexMetadata<'a>, RustlerError> {
+    use std::convert::TryFrom;
+
+    let tbi_path = index_path(&path);
+    let metadata = if std::path::Path::new(&tbi_path).exists() {
+        let index = handle_io_error!(tabix::read(&tbi_path));
+        let header = index.header();
+        let format = match header.format() {
+            tabix::index::header::Format::Generic(_) => atoms::generic(),
+            tabix::index::header::Format::Sam => atoms::sam(),
+            tabix::index::header::Format::Vcf => atoms::vcf(),
+        };
+        IndexMetadata {
+            kind: atoms::tbi(),
+            format: Some(format),
+            reference_sequence_name_index: Some(header.reference_sequence_name_index()),
+            start_position_index: Some(header.start_position_index()),
+            end_position_index: header.end_position_index(),
+            line_comment_prefix: Some(char::from(header.line_comment_prefix()).to_string()),
+            line_skip_count: Some(header.line_skip_count()),
+            contigs: header.reference_sequence_names().iter().cloned().collect(),
+            record_counts: record_counts_to_term(
+                env,
+                header.reference_sequence_names().iter(),
+                index_record_counts(&index),
+            )?,
+        }
+    } else {
+        let index = handle_io_error!(noodles_csi::read(format!("{}.csi", path)));
+        let aux = parse_csi_aux(index.aux());
+        let column = |i: usize| {
+            aux.as_ref()
+                .and_then(|aux| usize::try_from(aux.columns[i]).ok())
+                .filter(|&column| column > 0)
+        };
+        let names = aux
+            .as_ref()
+            .map(|aux| aux.names.clone())
+            .unwrap_or_default();
+        IndexMetadata {
+            kind: atoms::csi(),
+            format: aux.as_ref().and_then(|aux| index_format_atom(aux.format)),
+            reference_sequence_name_index: column(0),
+            start_position_index: column(1),
+            end_position_index: column(2),
+            line_comment_prefix: aux
+                .as_ref()
+                .and_then(|aux| u8::try_from(aux.meta).ok())
+                .map(|meta| char::from(meta).to_string()),
+            line_skip_count: aux.as_ref().and_then(|aux| u32::try_from(aux.skip).ok()),
+            record_counts: record_counts_to_term(env, names.iter(), index_record_counts(&index))?,
+            contigs: names,
+        }
+    };
+    Ok(metadata)
+}
+
+fn record_counts_to_term<'a, 'b>(
+    env: Env<'a>,
+    names: impl Iterator<Item = &'b String>,
+    counts: Vec<Option<u64>>,
+) -> Result<Term<'a>, RustlerError> {
+    let pairs: Vec<(&String, Option<u64>)> = names.zip(counts).collect();
+    Term::map_from_pairs(env, &pairs)
+}
+
+/// Opens a reader of its own on `path` and reads past the header, returning the reader and the
+/// number of header lines.
+fn reopen_past_header(path: &str) -> std::io::Result<(vcf::Reader<VcfInput>, usize)> {
+    let file = File::open(path)?;
+    let mut reader = vcf::Reader::new(VcfInput::from_file(file, HandleOptions::default())?);
+    let (_raw_header, header_lines) = read_raw_header(&mut reader)?;
+    Ok((reader, header_lines))
+}
+
+/// Runs `scan` over the records after the header without moving the handle, passing it the
+/// reader and a `next_line` that numbers each line it reads.
+///
+/// A handle opened from a file is scanned through a reader of its own on `path`. One with no
+/// path, over stdin or a binary, has nothing to reopen, so `scan` reads its stream instead and
+/// consumes whatever is left on it.
+fn scan_remaining<T, F>(handle: &VcfHandle, scan: F) -> Result<T, RustlerError>
+where
+    F: FnOnce(&mut vcf::Reader<VcfInput>, &mut dyn FnMut() -> usize) -> Result<T, RustlerError>,
+{
+    match &handle.path {
+        None => {
+            let mut stream = lock(&handle.stream);
+            scan(&mut stream, &mut || handle.next_line())
+        }
+        Some(path) => {
+            let (mut reader, mut line) = handle_io_error!(reopen_past_header(path));
+            scan(&mut reader, &mut || {
+                line += 1;
+                line
+            })
+        }
+    }
+}
+
+/// Reads every remaining record line without parsing it and returns how many there were.
+fn count_remaining_records<R: BufRead>(
+    reader: &mut vcf::Reader<R>,
+    mut next_line: impl FnMut() -> usize,
+) -> std::io::Result<u64> {
+    let mut count = 0;
+    while read_record_line(reader)?.is_some() {
+        next_line();
+        count += 1;
+    }
+    Ok(count)
+}
+
+/// Returns the number of records in the file.
+///
+/// A bgzipped file with a tabix index is counted from the index alone; anything else is counted
+/// by `scan_remaining`.
+#[rustler::nif(schedule = "DirtyIo")]
+fn count_records(handle: ResourceArc<VcfHandle>) -> Result<u64, RustlerError> {
+    if let (Some(path), true) = (&handle.path, handle.bgzf) {
+        match handle.tabix_index(path) {
+            Ok(index) => {
+                if let Some(count) = indexed_record_count(&index) {
+                    return Ok(count);
+                }
+            }
+            Err(err) if err.kind() == IoErrorKind::NotFound => {}
+            Err(err) => return Err(RustlerError::Term(Box::new(io_error_to_term(&err)))),
+        }
+    }
+
+    scan_remaining(&handle, |reader, next_line| {
+        Ok(handle_io_error!(count_remaining_records(reader, next_line)))
+    })
+}
+
+/// The virtual position of the first contig from which the index counts at least `n` records
+/// to the end of the file, or `None` if it cannot tell.
+fn indexed_tail_start(index: &tabix::Index, n: usize) -> Option<u64> {
+    use noodles_csi::{binning_index::ReferenceSequenceExt, BinningIndex};
+
+    let mut count = 0;
+    for reference_sequence in index.reference_sequences().iter().rev() {
+        let metadata = reference_sequence.metadata()?;
+        count += metadata.mapped_record_count() + metadata.unmapped_record_count();
+        if count >= n as u64 {
+            return Some(u64::from(metadata.start_position()));
+        }
+    }
+    None
+}
+
+/// Keeps the last `n` record lines read from `reader`, numbering them with `next_line`.
+fn tail_record_lines<R: BufRead>(
+    reader: &mut vcf::Reader<R>,
+    n: usize,
+    mut next_line: impl FnMut() -> usize,
+) -> std::io::Result<Vec<(usize, RecordText)>> {
+    let mut lines = VecDeque::with_capacity(n);
+    while let Some(text) = read_record_line(reader)? {
+        if lines.len() == n {
+            lines.pop_front();
+        }
+        lines.push_back((next_line(), text));
+    }
+    Ok(lines.into())
+}
+
+/// Returns the last `n` records of the file, scanned by `scan_remaining`.
+///
+/// Only the last `n` lines are held while scanning, and only they are parsed. A bgzipped file
+/// with a tabix index starts the scan at the last contigs that hold `n` records between them;
+/// records found that way report `line` as 0.
+#[rustler::nif(schedule = "DirtyIo")]
+fn tail<'a>(
+    env: Env<'a>,
+    handle: ResourceArc<VcfHandle>,
+    n: usize,
+) -> Result<Vec<VcfRecord<'a>>, RustlerError> {
+    if n == 0 {
+        return Ok(Vec::new());
+    }
+
+    let start = match (&handle.path, handle.bgzf) {
+        (Some(path), true) => match handle.tabix_index(path) {
+            Ok(index) => indexed_tail_start(&index, n),
+            Err(err) if err.kind() == IoErrorKind::NotFound => None,
+            Err(err) => return Err(RustlerError::Term(Box::new(io_error_to_term(&err)))),
+        },
+        _ => None,
+    };
+    let lines = scan_remaining(&handle, |reader, next_line| {
+        match start.and_then(|start| reader.get_mut().seek_to(start)) {
+            Some(result) => {
+                handle_io_error!(result);
+                Ok(handle_io_error!(tail_record_lines(reader, n, || 0)))
+            }
+            None => Ok(handle_io_error!(tail_record_lines(reader, n, next_line))),
+        }
+    })?;
+
+    let header = read_lock(&handle.header);
+    parse_record_lines(env, &header, &lines, &handle.read_options())
+}
+
+/// Returns the line of the first record that sorts before the one preceding it, if any.
+///
+/// Records sort by contig in `ranks` order, then by position. Contigs missing from `ranks` are
+/// added behind the others in the order they first appear.
+fn first_unsorted_line<R: BufRead>(
+    reader: &mut vcf::Reader<R>,
+    ranks: &mut HashMap<String, usize>,
+    mut next_line: impl FnMut() -> usize,
+) -> Result<Option<usize>, RustlerError> {
+    let mut previous = (0, 0);
+    while let Some(text) = handle_io_error!(read_record_line(reader)) {
+        let line = next_line();
+
+        let mut fields = line_text(line, &text)?.splitn(3, '\t');
+        let chromosome = fields.next().unwrap_or_default();
+        let position: usize = match fields.next().map(str::parse) {
+            Some(Ok(position)) => position,
+            _ => return Err(parse_error(line, "invalid position")),
+        };
+        let rank = match ranks.get(chromosome) {
+            Some(&rank) => rank,
+            None => {
+                let rank = ranks.len();
+                ranks.insert(chromosome.to_string(), rank);
+                rank
+            }
+        };
+
+        if (rank, position) < previous {
+            return Ok(Some(line));
+        }
+        previous = (rank, position);
+    }
+    Ok(None)
+}
+
+/// Whether the records can be assumed coordinate-sorted without scanning them: `true` for a
+/// bgzipped file with a tabix or CSI index, since neither can be built from unsorted input, and
+/// `:unknown` otherwise. `check_sorted` settles the question by reading the file.
+///
+/// Exposed to Elixir as `sorted?/1`, since NIF names cannot end in `?`.
+#[rustler::nif(schedule = "DirtyIo")]
+fn sorted<'a>(env: Env<'a>, handle: ResourceArc<VcfHandle>) -> Result<Term<'a>, RustlerError> {
+    let path = match (&handle.path, handle.bgzf) {
+        (Some(path), true) => path,
+        _ => return Ok(atoms::unknown().encode(env)),
+    };
+    let indexed = match handle.tabix_index(path) {
+        Ok(_index) => true,
+        Err(err) if err.kind() == IoErrorKind::NotFound => {
+            match noodles_csi::read(format!("{}.csi", path)) {
+                Ok(_index) => true,
+                Err(err) if err.kind() == IoErrorKind::NotFound => false,
+                Err(err) => return Err(RustlerError::Term(Box::new(io_error_to_term(&err)))),
+            }
+        }
+        Err(err) => return Err(RustlerError::Term(Box::new(io_error_to_term(&err)))),
+    };
+    match indexed {
+        true => Ok(true.encode(env)),
+        false => Ok(atoms::unknown().encode(env)),
+    }
+}
+
+/// Checks that records are sorted by the header's `##contig` order, then by position.
+///
+/// Returns `:ok` or `{:unsorted, line}` for the first record out of order. The file is scanned by
+/// `scan_remaining` without parsing records.
+#[rustler::nif(schedule = "DirtyIo")]
+fn check_sorted<'a>(
+    env: Env<'a>,
+    handle: ResourceArc<VcfHandle>,
+) -> Result<Term<'a>, RustlerError> {
+    let mut ranks: HashMap<String, usize> = read_lock(&handle.header)
+        .contigs()
+        .keys()
+        .enumerate()
+        .map(|(rank, name)| (name.to_string(), rank))
+        .collect();
+
+    let unsorted_line = scan_remaining(&handle, |reader, next_line| {
+        first_unsorted_line(reader, &mut ranks, next_line)
+    })?;
+
+    Ok(match unsorted_line {
+        Some(line) => (atoms::unsorted(), line).encode(env),
+        None => atoms::ok().encode(env),
+    })
+}
+
+/// Counts the remaining records per distinct CHROM value in first-seen order, numbering lines
+/// with `next_line`.
+fn contig_counts<R: BufRead>(
+    reader: &mut vcf::Reader<R>,
+    mut next_line: impl FnMut() -> usize,
+) -> Result<Vec<(String, u64)>, RustlerError> {
+    let mut counts: Vec<(String, u64)> = Vec::new();
+    while let Some(text) = handle_io_error!(read_record_line(reader)) {
+        let line = next_line();
+        let chromosome = line_text(line, &text)?
+            .split('\t')
+            .next()
+            .unwrap_or_default();
+        // Sorted input repeats the last contig, so try that before searching the rest.
+        match counts.last_mut() {
+            Some((contig, count)) if contig == chromosome => *count += 1,
+            _ => match counts
+                .iter_mut()
+                .find(|(contig, _count)| contig == chromosome)
+            {
+                Some((_contig, count)) => *count += 1,
+                None => counts.push((chromosome.to_string(), 1)),
+            },
+        }
+    }
+    Ok(counts)
+}
+
+/// Returns the contigs records actually use, in first-seen order, whether declared or not.
+///
+/// The file is scanned by `scan_remaining` without parsing records.
+#[rustler::nif(schedule = "DirtyIo")]
+fn observed_contigs(handle: ResourceArc<VcfHandle>) -> Result<Vec<String>, RustlerError> {
+    let counts = scan_remaining(&handle, |reader, next_line| {
+        contig_counts(reader, next_line)
+    })?;
+    Ok(counts.into_iter().map(|(contig, _count)| contig).collect())
+}
+
+/// Returns `{contig, count}` pairs for the contigs records use, in first-seen order, from a
+/// single scan; `Map.new/1` turns them into a map when order does not matter.
+///
+/// Scans like `observed_contigs` does.
+#[rustler::nif(schedule = "DirtyIo")]
+fn records_per_contig(handle: ResourceArc<VcfHandle>) -> Result<Vec<(String, u64)>, RustlerError> {
+    scan_remaining(&handle, |reader, next_line| {
+        contig_counts(reader, next_line)
+    })
+}
+
+/// How many of the remaining records are PASS, have no FILTER, or fail each named filter.
+#[derive(Default)]
+struct FilterCounts {
+    pass: u64,
+    none: u64,
+    /// Failing filters in first-seen order; a record failing several counts towards each.
+    fail: Vec<(String, u64)>,
+}
+
+fn count_filters<R: BufRead>(
+    reader: &mut vcf::Reader<R>,
+    header: &vcf::Header,
+    mut next_line: impl FnMut() -> usize,
+) -> Result<FilterCounts, RustlerError> {
+    let mut counts = FilterCounts::default();
+    while let Some(text) = handle_io_error!(read_record_line(reader)) {
+        let line = next_line();
+        let record = match vcf::record::Record::try_from_str(line_text(line, &text)?, header) {
+            Ok(record) => record,
+            Err(err) => return Err(parse_error(line, err)),
+        };
+        match record_filters(&record) {
+            VcfRecordFilters::None => counts.none += 1,
+            VcfRecordFilters::Pass => counts.pass += 1,
+            VcfRecordFilters::Fail(filters) => {
+                for filter in filters {
+                    match counts.fail.iter_mut().find(|(name, _)| *name == filter) {
+                        Some((_, count)) => *count += 1,
+                        None => counts.fail.push((filter, 1)),
+                    }
+                }
+            }
+        }
+    }
+    Ok(counts)
+}
+
+/// Counts how often each FILTER status occurs, as a map from `:pass`, `:none` (a missing
+/// FILTER) or a failing filter's name to the number of records carrying it.
+///
+/// Statuses no record carries are left out. The file is scanned by `scan_remaining`.
+#[rustler::nif(schedule = "DirtyIo")]
+fn filter_histogram<'a>(
+    env: Env<'a>,
+    handle: ResourceArc<VcfHandle>,
+) -> Result<Term<'a>, RustlerError> {
+    let header = read_lock(&handle.header);
+    let counts = scan_remaining(&handle, |reader, next_line| {
+        count_filters(reader, &header, next_line)
+    })?;
+
+    let mut pairs = Vec::with_capacity(counts.fail.len() + 2);
+    if counts.pass > 0 {
+        pairs.push((atoms::pass().encode(env), counts.pass.encode(env)));
+    }
+    if counts.none > 0 {
+        pairs.push((atoms::none().encode(env), counts.none.encode(env)));
+    }
+    for (filter, count) in &counts.fail {
+        pairs.push((filter.encode(env), count.encode(env)));
+    }
+    Term::map_from_pairs(env, &pairs)
+}
+
+/// A sample's `GT` alleles in sorted order, so that `1/0` and `0|1` compare equal, or `None` when
+/// the call is absent or every allele is missing.
+fn sorted_call(record: &vcf::record::Record, sample_index: usize) -> Option<Vec<Option<usize>>> {
+    let call = record.genotypes().get(sample_index)?.genotype()?.ok()?;
+    let mut alleles: Vec<Option<usize>> = call.iter().map(|allele| allele.position()).collect();
+    if alleles.iter().all(Option::is_none) {
+        return None;
+    }
+    alleles.sort_unstable();
+    Some(alleles)
+}
+
+fn compare_genotypes<R: BufRead>(
+    reader: &mut vcf::Reader<R>,
+    header: &vcf::Header,
+    samples: (usize, usize),
+    mut next_line: impl FnMut() -> usize,
+) -> Result<GenotypeConcordance, RustlerError> {
+    let mut counts = GenotypeConcordance::default();
+    while let Some(text) = handle_io_error!(read_record_line(reader)) {
+        let line = next_line();
+        let record = match vcf::record::Record::try_from_str(line_text(line, &text)?, header) {
+            Ok(record) => record,
+            Err(err) => return Err(parse_error(line, err)),
+        };
+        match (
+            sorted_call(&record, samples.0),
+            sorted_call(&record, samples.1),
+        ) {
+            (None, None) => counts.both_missing += 1,
+            (Some(a), Some(b)) if a == b => counts.matching += 1,
+            (Some(_a), Some(_b)) => counts.mismatching += 1,
+            _ => counts.one_missing += 1,
+        }
+    }
+    let called = counts.matching + counts.mismatching;
+    if called > 0 {
+        counts.concordance = Some(counts.matching as f64 / called as f64);
+    }
+    Ok(counts)
+}
+
+/// Compares the `GT` calls of two samples at every record, ignoring phasing and allele order.
+///
+/// A sample missing from the header fails with `{:not_found, sample_name}`. The file is scanned
+/// by `scan_remaining`.
+#[rustler::nif(schedule = "DirtyIo")]
+fn genotype_concordance(
+    handle: ResourceArc<VcfHandle>,
+    sample_a: String,
+    sample_b: String,
+) -> Result<GenotypeConcordance, RustlerError> {
+    let header = read_lock(&handle.header);
+    let sample_index = |sample_name: String| {
+        header
+            .sample_names()
+            .get_index_of(&sample_name)
+            .ok_or_else(|| RustlerError::Term(Box::new((atoms::not_found(), sample_name))))
+    };
+    let samples = (sample_index(sample_a)?, sample_index(sample_b)?);
+
+    scan_remaining(&handle, |reader, next_line| {
+        compare_genotypes(reader, &header, samples, next_line)
+    })
+}
+
+fn distinct_phase_sets<R: BufRead>(
+    reader: &mut vcf::Reader<R>,
+    header: &vcf::Header,
+    sample_index: usize,
+    mut next_line: impl FnMut() -> usize,
+) -> Result<Vec<i32>, RustlerError> {
+    use vcf::record::genotypes::genotype::field::Value;
+
+    let mut seen = Vec::new();
+    while let Some(text) = handle_io_error!(read_record_line(reader)) {
+        let line = next_line();
+        let record = match vcf::record::Record::try_from_str(line_text(line, &text)?, header) {
+            Ok(record) => record,
+            Err(err) => return Err(parse_error(line, err)),
+        };
+        let phase_set = record
+            .genotypes()
+            .get(sample_index)
+            .and_then(|genotype| genotype.get(&vcf::header::format::Key::PhaseSet))
+            .and_then(|field| field.value());
+        if let Some(&Value::Integer(phase_set)) = phase_set {
+            if !seen.contains(&phase_set) {
+                seen.push(phase_set);
+            }
+        }
+    }
+    Ok(seen)
+}
+
+/// Returns the distinct `PS` phase-set IDs of a sample in first-seen order, skipping records
+/// where it is missing.
+///
+/// Fails with `{:not_found, sample_name}` when the sample is not in the header. The file is scanned
+/// by `scan_remaining`.
+#[rustler::nif(schedule = "DirtyIo")]
+fn phase_sets(
+    handle: ResourceArc<VcfHandle>,
+    sample_name: String,
+) -> Result<Vec<i32>, RustlerError> {
+    let header = read_lock(&handle.header);
+    let sample_index = match header.sample_names().get_index_of(&sample_name) {
+        Some(sample_index) => sample_index,
+        None => {
+            return Err(RustlerError::Term(Box::new((
+                atoms::not_found(),
+                sample_name,
+            ))))
+        }
+    };
+
+    scan_remaining(&handle, |reader, next_line| {
+        distinct_phase_sets(reader, &header, sample_index, next_line)
+    })
+}
+
+/// Reads up to `count` records on `contig`, leaving the first record past it unread.
+///
+/// A bgzipped file with a tabix index jumps straight to the contig's first record unless the
+/// stream is already inside the contig, so asking again once its last record has been read
+/// starts it over. The lines jumped over are not counted, so records from then on report `line`
+/// as 0.
+///
+/// Otherwise records on other contigs are skipped until the contig is reached. If the input ends
+/// first, the contig may lie behind the stream, so the handle is rewound and scanned once more
+/// from the first record; a contig with no records at all leaves it at the end of the input.
+#[rustler::nif(schedule = "DirtyIo")]
+fn get_records_for_contig<'a>(
+    env: Env<'a>,
+    handle: ResourceArc<VcfHandle>,
+    contig: String,
+    count: usize,
+) -> Result<Vec<VcfRecord<'a>>, RustlerError> {
+    use noodles_csi::BinningIndex;
+
+    let index = match (&handle.path, handle.bgzf) {
+        (Some(path), true) => match handle.tabix_index(path) {
+            Ok(index) => Some(index),
+            Err(err) if err.kind() == IoErrorKind::NotFound => None,
+            Err(err) => return Err(RustlerError::Term(Box::new(io_error_to_term(&err)))),
+        },
+        _ => None,
+    };
+    let header = read_lock(&handle.header);
+    if !header.contigs().contains_key(contig.as_str()) {
+        return Err(RustlerError::Term(Box::new(atoms::not_found())));
+    }
+
+    let indexed = index.is_some();
+    let mut stream = lock(&handle.stream);
+    // Whether the stream is known to be inside the contig, so any other contig ends it.
+    let mut on_contig = false;
+    if let Some(index) = index {
+        let reference_sequence_id = match index
+            .header()
+            .reference_sequence_names()
+            .get_index_of(contig.as_str())
+        {
+            Some(reference_sequence_id) => reference_sequence_id,
+            // Declared in the header, but without a single record to index.
+            None => return Ok(Vec::new()),
+        };
+        let chunks = handle_io_error!(index.query(reference_sequence_id, ..));
+        let start = chunks.iter().map(|chunk| u64::from(chunk.start())).min();
+        let end = chunks.iter().map(|chunk| u64::from(chunk.end())).max();
+        let (start, end) = match (start, end) {
+            (Some(start), Some(end)) => (start, end),
+            _ => return Ok(Vec::new()),
+        };
+        if let Some(position) = stream.get_mut().position() {
+            if !(start..end).contains(&handle_io_error!(position)) {
+                if let Some(result) = stream.get_mut().seek_to(start) {
+                    handle_io_error!(result);
+                }
+                handle.line_known.store(false, Ordering::Relaxed);
+            }
+        }
+        on_contig = true;
+    }
+
+    let mut step = |buf: &str| {
+        if buf.split('\t').next() == Some(contig.as_str()) {
+            on_contig = true;
+            ScanStep::Take
+        } else if on_contig {
+            ScanStep::Stop
+        } else {
+            ScanStep::Skip
+        }
+    };
+    let mut lines = scan_record_lines(&handle, &mut stream, count, &mut step)?;
+    // Nothing taken means the scan never met the contig and ran to the end of the input.
+    if lines.is_empty() && count > 0 && !indexed {
+        rewind_stream(&handle, &mut stream)?;
+        lines = scan_record_lines(&handle, &mut stream, count, &mut step)?;
+    }
+    drop(stream);
+
+    parse_record_lines(env, &header, &lines, &handle.read_options())
+}
+
+/// Reads up to `count` records on `contig` with a position in `start..=end`.
+///
+/// Records outside the window are skipped without being parsed, and on a sorted file the scan
+/// ends at the first record past it, leaving that record unread.
+#[rustler::nif(schedule = "DirtyIo")]
+fn get_records_in_range<'a>(
+    env: Env<'a>,
+    handle: ResourceArc<VcfHandle>,
+    contig: String,
+    start: usize,
+    end: usize,
+    count: usize,
+) -> Result<Vec<VcfRecord<'a>>, RustlerError> {
+    let header = read_lock(&handle.header);
+    if !header.contigs().contains_key(contig.as_str()) {
+        return Err(RustlerError::Term(Box::new(atoms::not_found())));
+    }
+
+    let mut stream = lock(&handle.stream);
+    let mut on_contig = false;
+    let lines = scan_record_lines(&handle, &mut stream, count, |buf| {
+        let mut fields = buf.splitn(3, '\t');
+        if fields.next() != Some(contig.as_str()) {
+            return if on_contig {
+                ScanStep::Stop
+            } else {
+                ScanStep::Skip
+            };
+        }
+        on_contig = true;
+        match fields
+            .next()
+            .and_then(|position| position.parse::<usize>().ok())
+        {
+            Some(position) if position < start => ScanStep::Skip,
+            Some(position) if position > end => ScanStep::Stop,
+            // An unreadable position is taken so that parsing reports it with its line.
+            _ => ScanStep::Take,
+        }
+    })?;
+    drop(stream);
+
+    parse_record_lines(env, &header, &lines, &handle.read_options())
+}
+
+/// What a scan does with a raw record line, decided before the line is parsed.
+enum ScanStep {
+    Take,
+    Skip,
+    /// Leave the line unread and end the scan.
+    Stop,
+}
+
+/// Reads up to `count` lines that `step` takes, counting skipped lines, until it says stop.
+///
+/// Stopping seeks back over the line just read, so forward-only input cannot be scanned. A line
+/// that is not valid UTF-8 is taken without asking `step`, so that it is reported.
+fn scan_record_lines(
+    handle: &VcfHandle,
+    stream: &mut vcf::Reader<VcfInput>,
+    count: usize,
+    mut step: impl FnMut(&str) -> ScanStep,
+) -> Result<Vec<(usize, RecordText)>, RustlerError> {
+    let mut lines = Vec::with_capacity(count);
+    while lines.len() < count {
+        let position = match stream.get_mut().position() {
+            Some(position) => handle_io_error!(position),
+            None => return Err(RustlerError::Term(Box::new(atoms::not_seekable()))),
+        };
+        let text = match handle_io_error!(read_record_line(stream)) {
+            Some(text) => text,
+            None => break,
+        };
+        let scan_step = match &text {
+            Ok(buf) => step(buf),
+            Err(_invalid) => ScanStep::Take,
+        };
+        match scan_step {
+            ScanStep::Take => lines.push((handle.next_line(), text)),
+            ScanStep::Skip => {
+                handle.line.fetch_add(1, Ordering::Relaxed);
+            }
+            ScanStep::Stop => {
+                if let Some(result) = stream.get_mut().seek_to(position) {
+                    handle_io_error!(result);
+                }
+                break;
+            }
+        }
+    }
+
+    Ok(lines)
+}
+
+/// A physical line number paired with why that line failed to parse.
+type LineError = (usize, String);
+
+/// Like `get_records`, but collects `{line, reason}` for bad lines instead of failing the batch.
+///
+/// Bad lines do not count towards `count`: reading goes on until `count` records parse or the
+/// input ends.
+#[rustler::nif]
+fn get_records_lenient<'a>(
+    env: Env<'a>,
+    handle: ResourceArc<VcfHandle>,
+    count: usize,
+) -> Result<(Vec<VcfRecord<'a>>, Vec<LineError>), RustlerError> {
+    read_records_lenient(env, &handle, count, &handle.read_options())
+}
+
+/// Reads until `count` records parse or the input ends, setting bad lines aside as errors.
+fn read_records_lenient<'a>(
+    env: Env<'a>,
+    handle: &VcfHandle,
+    count: usize,
+    options: &ReadOptions,
+) -> Result<(Vec<VcfRecord<'a>>, Vec<LineError>), RustlerError> {
+    let header = read_lock(&handle.header);
+    let mut records = Vec::with_capacity(count);
+    let mut errors = Vec::new();
+
+    while records.len() < count {
+        let wanted = count - records.len();
+        let lines = read_record_lines(handle, Some(wanted))?;
+        let parsed_records: Vec<_> = lines
+            .par_iter()
+            .map(|(_line, text)| {
+                let buf = match text {
+                    Ok(buf) => buf,
+                    Err(invalid) => {
+                        return Err(format!("invalid UTF-8 at byte {}", invalid.valid_up_to))
+                    }
+                };
+                match options.oversized_field(&header, buf) {
+                    Some((column, len)) => Err(format!(
+                        "{} is {} bytes, over max_field_len of {}",
+                        column,
+                        len,
+                        options.max_field_len.unwrap_or_default()
+                    )),
+                    None => {
+                        vcf::record::Record::try_from_str(buf, &header).map_err(|e| e.to_string())
+                    }
+                }
+            })
+            .collect();
+
+        for ((line, _buf), parsed_record) in lines.iter().zip(parsed_records) {
+            match parsed_record {
+                Ok(record) => {
+                    records.push(record_to_struct(env, &header, &record, *line, options)?)
+                }
+                Err(reason) => errors.push((*line, reason)),
+            }
+        }
+        if lines.len() < wanted {
+            break;
+        }
+    }
+
+    Ok((records, errors))
+}
+
+#[rustler::nif(schedule = "DirtyCpu")]
+fn get_all_records<'a>(
+    env: Env<'a>,
+    handle: ResourceArc<VcfHandle>,
+) -> Result<Vec<VcfRecord<'a>>, RustlerError> {
+    let header = read_lock(&handle.header);
+    let lines = read_record_lines(&handle, None)?;
+
+    parse_record_lines(env, &header, &lines, &handle.read_options())
+}
+
+/// Opens an existing uncompressed VCF to append records after its last line.
+///
+/// Records are encoded against the file's own header, which is left untouched.
+#[rustler::nif(schedule = "DirtyIo")]
+fn open_writer_append(path: String) -> Result<ResourceArc<VcfWriterHandle>, RustlerError> {
+    let file = handle_io_error!(File::open(&path));
+    let input = handle_io_error!(VcfInput::from_file(file, HandleOptions::default()));
+    // Plain text after gzip members would leave a file nothing can read end to end.
+    if !matches!(input, VcfInput::File(_)) {
+        return Err(RustlerError::Term(Box::new(atoms::compressed())));
+    }
+    let header = read_checked_header(&mut vcf::Reader::new(input))?;
+
+    let mut file = handle_io_error!(OpenOptions::new().read(true).append(true).open(&path));
+    // Without a final newline the first appended record would run into the last line.
+    let mut last_byte = [0; 1];
+    if handle_io_error!(file.seek(SeekFrom::End(0))) > 0 {
+        handle_io_error!(file.seek(SeekFrom::End(-1)));
+        handle_io_error!(file.read_exact(&mut last_byte));
+        if last_byte != *b"\n" {
+            handle_io_error!(file.write_all(b"\n"));
+        }
+    }
+
+    Ok(ResourceArc::new(VcfWriterHandle {
+        header,
+        stream: sync::Mutex::new(vcf::Writer::new(VcfOutput::File(BufWriter::new(file)))),
+    }))
+}
+
+/// Creates `path` for writing, as bgzf when it ends in `.gz`.
+fn create_output(path: &str) -> std::io::Result<VcfOutput> {
+    let file = File::create(path)?;
+    if path.ends_with(".gz") {
+        Ok(VcfOutput::Bgzf(bgzf::Writer::new(file)))
+    } else {
+        Ok(VcfOutput::File(BufWriter::new(file)))
+    }
+}
+
+/// Creates `path` with the handle's header; a path ending in `.gz` is written as bgzf.
+///
+/// Bgzf output is only complete once `close_writer` (or the writer's garbage collection) has
+/// appended the end-of-file block.
+#[rustler::nif(schedule = "DirtyIo")]
+fn get_writer(
+    path: String,
+    handle: ResourceArc<VcfHandle>,
+) -> Result<ResourceArc<VcfWriterHandle>, RustlerError> {
+    let header = read_lock(&handle.header).clone();
+    create_writer(&path, header)
+}
+
+#[derive(Default)]
+struct WriterOptions {
+    /// Sample columns to keep, in header order; `None` keeps them all.
+    select_samples: Option<Vec<String>>,
+}
+
+fn decode_writer_options(opts: Vec<(Atom, Term)>) -> Result<WriterOptions, RustlerError> {
+    let mut options = WriterOptions::default();
+    for (key, value) in opts {
+        if key == atoms::select_samples() {
+            options.select_samples = Some(value.decode()?);
+        } else {
+            return Err(RustlerError::BadArg);
+        }
+    }
+    Ok(options)
+}
+
+/// `get_writer/2` with options. `select_samples: names` writes only those samples' columns,
+/// dropping the rest from the header and from every record; with no names left the output is
+/// sites-only. A name the handle does not have fails with `{:not_found, sample_name}`.
+#[rustler::nif(name = "get_writer", schedule = "DirtyIo")]
+fn get_writer_with_opts(
+    path: String,
+    handle: ResourceArc<VcfHandle>,
+    opts: Vec<(Atom, Term)>,
+) -> Result<ResourceArc<VcfWriterHandle>, RustlerError> {
+    let options = decode_writer_options(opts)?;
+    let mut header = read_lock(&handle.header).clone();
+    if let Some(selected) = options.select_samples {
+        if let Some(sample_name) = selected
+            .iter()
+            .find(|sample_name| !header.sample_names().contains(*sample_name))
+        {
+            return Err(RustlerError::Term(Box::new((
+                atoms::not_found(),
+                sample_name.clone(),
+            ))));
+        }
+        header
+            .sample_names_mut()
+            .retain(|sample_name| selected.contains(sample_name));
+    }
+    create_writer(&path, header)
+}
+
+fn create_writer(
+    path: &str,
+    header: vcf::Header,
+) -> Result<ResourceArc<VcfWriterHandle>, RustlerError> {
+    let mut writer = vcf::Writer::new(handle_io_error!(create_output(path)));
+    handle_io_error!(writer.write_header(&header));
+    handle_io_error!(writer.get_mut().flush());
+
+    Ok(ResourceArc::new(VcfWriterHandle {
+        header,
+        stream: sync::Mutex::new(writer),
+    }))
+}
+
+/// Filters applied by `copy_records`, decoded from an Elixir keyword list.
+#[derive(Clone, Copy, Default)]
+struct CopyOptions {
+    /// Only copy records whose FILTER column is `PASS`.
+    pass_only: bool,
+}
+
+fn decode_copy_options(opts: Vec<(Atom, Term)>) -> Result<CopyOptions, RustlerError> {
+    let mut options = CopyOptions::default();
+    for (key, value) in opts {
+        if key == atoms::pass_only() {
+            options.pass_only = value.decode()?;
+        } else {
+            return Err(RustlerError::BadArg);
+        }
+    }
+    Ok(options)
+}
+
+/// Where each of the writer's samples sits among the reader's, or `None` when both have the same
+/// samples and records can be copied as they are.
+fn writer_sample_columns(
+    header: &vcf::Header,
+    writer_header: &vcf::Header,
+) -> Result<Option<Vec<usize>>, RustlerError> {
+    if header.sample_names() == writer_header.sample_names() {
+        return Ok(None);
+    }
+    let mut columns = Vec::with_capacity(writer_header.sample_names().len());
+    for sample_name in writer_header.sample_names() {
+        match header.sample_names().get_index_of(sample_name) {
+            Some(i) => columns.push(i),
+            None => {
+                return Err(RustlerError::Term(Box::new((
+                    atoms::not_found(),
+                    sample_name.clone(),
+                ))))
+            }
+        }
+    }
+    Ok(Some(columns))
+}
+
+/// Rebuilds a record line with only the sample columns at `columns`, dropping FORMAT as well when
+/// none are left.
+fn select_sample_columns(line: &str, columns: &[usize]) -> String {
+    let fields: Vec<&str> = line.split('\t').collect();
+    if fields.len() <= 9 || columns.is_empty() {
+        return fields[..fields.len().min(8)].join("\t");
+    }
+    let samples = columns.iter().filter_map(|&i| fields.get(9 + i));
+    fields[..9]
+        .iter()
+        .chain(samples)
+        .copied()
+        .collect::<Vec<_>>()
+        .join("\t")
+}
+
+/// Streams the reader's remaining records into the writer without building any terms.
+///
+/// When the writer has different samples, as after `select_samples:`, each record keeps only the
+/// writer's sample columns; a writer sample the reader lacks fails with `{:not_found, name}`.
+/// Returns how many records were written.
+#[rustler::nif(schedule = "DirtyIo")]
+fn copy_records(
+    handle: ResourceArc<VcfHandle>,
+    writer: ResourceArc<VcfWriterHandle>,
+    opts: Vec<(Atom, Term)>,
+) -> Result<usize, RustlerError> {
+    const BATCH_SIZE: usize = 1024;
+
+    let options = decode_copy_options(opts)?;
+    let header = read_lock(&handle.header);
+    let sample_columns = writer_sample_columns(&header, &writer.header)?;
+    let mut stream = writer.open_stream()?;
+    let mut copied = 0;
+
+    loop {
+        let lines = read_record_lines(&handle, Some(BATCH_SIZE))?;
+        for (line, text) in &lines {
+            let buf = match line_text(*line, text) {
+                Ok(buf) => buf,
+                Err(err) => {
+                    handle_io_error!(stream.get_mut().flush());
+                    return Err(err);
+                }
+            };
+            let parsed = match &sample_columns {
+                Some(columns) => vcf::record::Record::try_from_str(
+                    &select_sample_columns(buf, columns),
+                    &writer.header,
+                ),
+                None => vcf::record::Record::try_from_str(buf, &header),
+            };
+            let record = match parsed {
+                Ok(record) => record,
+                Err(err) => {
+                    handle_io_error!(stream.get_mut().flush());
+                    return Err(parse_error(*line, err));
+                }
+            };
+            if options.pass_only && !matches!(record.filters(), Some(vcf::record::Filters::Pass)) {
+                continue;
+            }
+            handle_io_error!(stream.write_record(&record));
+            copied += 1;
+        }
+        if lines.len() < BATCH_SIZE {
+            break;
+        }
+    }
+
+    handle_io_error!(stream.get_mut().flush());
+    Ok(copied)
+}
+
+/// A float as JSON, by way of its shortest decimal form so that `0.1` does not come out as
+/// `0.10000000149011612`; NaN becomes `null`.
+fn json_float(n: f32) -> serde_json::Value {
+    n.to_string()
+        .parse::<f64>()
+        .ok()
+        .and_then(serde_json::Number::from_f64)
+        .map_or(serde_json::Value::Null, serde_json::Value::Number)
+}
+
+fn info_value_to_json(value: &vcf::record::info::field::Value) -> serde_json::Value {
+    use vcf::record::info::field::Value;
+
+    match value {
+        Value::Integer(n) => (*n).into(),
+        Value::Float(n) => json_float(*n),
+        Value::Flag => true.into(),
+        Value::Character(c) => c.to_string().into(),
+        Value::String(s) => s.as_str().into(),
+        Value::IntegerArray(values) => values.iter().copied().collect(),
+        Value::FloatArray(values) => values.iter().map(|value| value.map(json_float)).collect(),
+        Value::CharacterArray(values) => {
+            values.iter().map(|value| value.map(String::from)).collect()
+        }
+        Value::StringArray(values) => values.iter().cloned().collect(),
+    }
+}
+
+fn genotype_value_to_json(
+    value: &vcf::record::genotypes::genotype::field::Value,
+) -> serde_json::Value {
+    use vcf::record::genotypes::genotype::field::Value;
+
+    match value {
+        Value::Integer(n) => (*n).into(),
+        Value::Float(n) => json_float(*n),
+        Value::Character(c) => c.to_string().into(),
+        Value::String(s) => s.as_str().into(),
+        Value::IntegerArray(values) => values.iter().copied().collect(),
+        Value::FloatArray(values) => values.iter().map(|value| value.map(json_float)).collect(),
+        Value::CharacterArray(values) => {
+            values.iter().map(|value| value.map(String::from)).collect()
+        }
+        Value::StringArray(values) => values.iter().cloned().collect(),
+    }
+}
+
+fn genotype_to_json(
+    genotype: &vcf::record::genotypes::Genotype,
+    options: &ReadOptions,
+) -> serde_json::Value {
+    let mut fields = serde_json::Map::new();
+    for (key, field) in genotype.iter() {
+        if !options.includes_format(key.as_ref()) {
+            continue;
+        }
+        let value = match (key, field.value()) {
+            (_key, None) => serde_json::Value::Null,
+            (vcf::header::format::Key::Genotype, Some(value)) => match genotype.genotype() {
+                Some(Ok(call)) => {
+                    let call = genotype_call_to_struct(&call);
+                    serde_json::json!({ "alleles": call.alleles, "phased": call.phased })
+                }
+                _ => genotype_value_to_json(value),
+            },
+            (_key, Some(value)) => genotype_value_to_json(value),
+        };
+        fields.insert(key.to_string(), value);
+    }
+    fields.into()
+}
+
+/// The JSON counterpart of `record_to_struct`, covering the columns as read: INFO and FORMAT
+/// values are typed from the header, `GT` becomes `{"alleles": [...], "phased": bool}` and
+/// missing values are `null`.
+fn record_to_json(
+    header: &vcf::Header,
+    record: &vcf::record::Record,
+    options: &ReadOptions,
+) -> serde_json::Value {
+    let filters = match record_filters(record) {
+        VcfRecordFilters::None => serde_json::Value::Null,
+        VcfRecordFilters::Pass => "PASS".into(),
+        VcfRecordFilters::Fail(filters) => filters.into(),
+    };
+    let info: serde_json::Map<String, serde_json::Value> = record
+        .info()
+        .as_ref()
+        .iter()
+        .filter(|(key, _field)| options.includes_info(key.as_ref()))
+        .map(|(key, field)| {
+            let value = field
+                .value()
+                .map_or(serde_json::Value::Null, info_value_to_json);
+            (key.to_string(), value)
+        })
+        .collect();
+
+    let format: Vec<String> = match record.genotypes().is_empty() {
+        true => Vec::new(),
+        false => record
+            .format()
+            .iter()
+            .filter(|key| options.includes_format(key.as_ref()))
+            .map(|key| key.to_string())
+            .collect(),
+    };
+    let genotypes: serde_json::Map<String, serde_json::Value> = header
+        .sample_names()
+        .iter()
+        .enumerate()
+        .map(|(i, sample_name)| {
+            let genotype = record
+                .genotypes()
+                .get(i)
+                .map_or(serde_json::Value::Null, |genotype| {
+                    genotype_to_json(genotype, options)
+                });
+            (sample_name.clone(), genotype)
+        })
+        .collect();
+
+    serde_json::json!({
+        "chromosome": record.chromosome().to_string(),
+        "position": usize::from(record.position()),
+        "ids": record.ids().iter().map(|id| id.to_string()).collect::<Vec<_>>(),
+        "reference_bases": record.reference_bases().to_string(),
+        "alternate_bases": record
+            .alternate_bases()
+            .iter()
+            .map(|allele| allele.to_string())
+            .collect::<Vec<_>>(),
+        "quality_score": record.quality_score().map(|score| json_float(f32::from(score))),
+        "filters": filters,
+        "info": info,
+        "format": format,
+        "genotypes": genotypes,
+    })
+}
+
+/// Writes the handle's remaining records to `output_path` as newline-delimited JSON, one object
+/// per record, and returns how many were written.
+///
+/// Records are parsed and serialized on the Rust side, so none of them cross into the BEAM. Of the
+/// read options, `info:` and `format:` narrow the keys written and `max_field_len:` applies as it
+/// does for reads; the rest only shape Elixir terms and are ignored. A path ending in `.gz` is
+/// written as bgzf. A record that fails to parse stops the export with `{:parse, line, reason}`,
+/// leaving the records before it in the file.
+#[rustler::nif(schedule = "DirtyIo")]
+fn export_json(
+    handle: ResourceArc<VcfHandle>,
+    output_path: String,
+    opts: Vec<(Atom, Term)>,
+) -> Result<u64, RustlerError> {
+    const BATCH_SIZE: usize = 1024;
+
+    let options = decode_read_options(&handle, opts)?;
+    let header = read_lock(&handle.header);
+    let mut output = handle_io_error!(create_output(&output_path));
+    let mut exported = 0;
+
+    loop {
+        let lines = read_record_lines(&handle, Some(BATCH_SIZE))?;
+        let mut texts = Vec::with_capacity(lines.len());
+        for (line, text) in &lines {
+            let checked = line_text(*line, text).and_then(|buf| {
+                options
+                    .check_field_lengths(&header, *line, buf)
+                    .map(|()| buf)
+            });
+            match checked {
+                Ok(buf) => texts.push((*line, buf)),
+                Err(err) => {
+                    handle_io_error!(output.finish());
+                    return Err(err);
+                }
+            }
+        }
+        let parsed_records: Vec<_> = texts
+            .par_iter()
+            .map(|(_line, buf)| {
+                vcf::record::Record::try_from_str(buf, &header)
+                    .map(|record| record_to_json(&header, &record, &options))
+            })
+            .collect();
+        for ((line, _buf), parsed_record) in lines.iter().zip(parsed_records) {
+            let json = match parsed_record {
+                Ok(json) => json,
+                Err(err) => {
+                    handle_io_error!(output.finish());
+                    return Err(parse_error(*line, err));
+                }
+            };
+            handle_io_error!(serde_json::to_writer(&mut output, &json).map_err(IoError::from));
+            handle_io_error!(output.write_all(b"\n"));
+            exported += 1;
+        }
+        if lines.len() < BATCH_SIZE {
+            break;
+        }
+    }
+
+    handle_io_error!(output.finish());
+    Ok(exported)
+}
+
+fn is_bcf_path(path: &str) -> bool {
+    path.ends_with(".bcf")
+}
+
+/// Where `convert` writes to. BCF stores strings as indices into the header's string maps.
+enum ConvertOutput {
+    Vcf(vcf::Writer<VcfOutput>),
+    Bcf(
+        Box<bcf::Writer<bgzf::Writer<File>>>,
+        bcf::header::StringMaps,
+    ),
+}
+
+impl ConvertOutput {
+    fn create(path: &str, header: &vcf::Header) -> std::io::Result<Self> {
+        if is_bcf_path(path) {
+            let mut writer = bcf::Writer::new(File::create(path)?);
+            writer.write_file_format()?;
+            writer.write_header(header)?;
+            Ok(ConvertOutput::Bcf(
+                Box::new(writer),
+                bcf::header::StringMaps::from(header),
+            ))
+        } else {
+            let mut writer = vcf::Writer::new(create_output(path)?);
+            writer.write_header(header)?;
+            Ok(ConvertOutput::Vcf(writer))
+        }
+    }
+
+    fn write_record(
+        &mut self,
+        header: &vcf::Header,
+        record: &vcf::record::Record,
+    ) -> std::io::Result<()> {
+        match self {
+            ConvertOutput::Vcf(writer) => writer.write_record(record),
+            ConvertOutput::Bcf(writer, string_maps) => {
+                writer.write_vcf_record(header, string_maps, record)
+            }
+        }
+    }
+
+    /// Flushes the output and, for bgzf and BCF, appends the end-of-file block.
+    fn finish(self) -> std::io::Result<()> {
+        match self {
+            ConvertOutput::Vcf(writer) => writer.into_inner().finish(),
+            ConvertOutput::Bcf(mut writer, _string_maps) => writer.try_finish(),
+        }
+    }
+}
+
+/// Writes `record`, reporting one the output cannot represent as
+/// `{:invalid_record, index, reason}` like `write_records` does.
+fn write_converted(
+    output: &mut ConvertOutput,
+    header: &vcf::Header,
+    record: &vcf::record::Record,
+    index: u64,
+) -> Result<(), RustlerError> {
+    match output.write_record(header, record) {
+        Ok(()) => Ok(()),
+        Err(err) if err.kind() == IoErrorKind::InvalidInput => Err(RustlerError::Term(Box::new((
+            atoms::invalid_record(),
+            index,
+            err.to_string(),
+        )))),
+        Err(ref err) => Err(RustlerError::Term(Box::new(io_error_to_term(err)))),
+    }
+}
+
+/// Streams every record of `input_path` into `output_path`, keeping the header, and returns
+/// how many records were converted.
+///
+/// A path ending in `.bcf` is read or written as BCF and anything else as VCF, with VCF output
+/// ending in `.gz` written as bgzf like `get_writer` does. A BCF record that cannot be decoded
+/// fails with `{:parse, n, reason}`, `n` counting records from 1.
+#[rustler::nif(schedule = "DirtyIo")]
+fn convert(input_path: String, output_path: String) -> Result<u64, RustlerError> {
+    let file = handle_io_error!(File::open(&input_path));
+    let mut converted = 0;
+
+    if is_bcf_path(&input_path) {
+        let mut reader = bcf::Reader::new(file);
+        handle_io_error!(reader.read_file_format());
+        let raw_header = handle_io_error!(reader.read_header());
+        let header = match raw_header.parse::<vcf::Header>() {
+            Ok(header) => header,
+            Err(err) => return Err(header_error(&raw_header, err)),
+        };
+        check_file_format(&header)?;
+        let string_maps = match raw_header.parse::<bcf::header::StringMaps>() {
+            Ok(string_maps) => string_maps,
+            Err(err) => {
+                return Err(RustlerError::Term(Box::new((
+                    atoms::header(),
+                    header_error_line(&raw_header),
+                    err.to_string(),
+                ))))
+            }
+        };
+
+        let mut output = handle_io_error!(ConvertOutput::create(&output_path, &header));
+        for result in reader.records() {
+            let record = handle_io_error!(result);
+            let record = match record.try_into_vcf_record(&header, &string_maps) {
+                Ok(record) => record,
+                Err(err) => return Err(parse_error(converted as usize + 1, err)),
+            };
+            write_converted(&mut output, &header, &record, converted)?;
+            converted += 1;
+        }
+        handle_io_error!(output.finish());
+    } else {
+        let input = handle_io_error!(VcfInput::from_file(file, HandleOptions::default()));
+        let mut reader = vcf::Reader::new(input);
+        let (raw_header, header_lines) = handle_io_error!(read_raw_header(&mut reader));
+        let header = match raw_header.parse::<vcf::Header>() {
+            Ok(header) => header,
+            Err(err) => return Err(header_error(&raw_header, err)),
+        };
+        check_file_format(&header)?;
+
+        let mut output = handle_io_error!(ConvertOutput::create(&output_path, &header));
+        let mut line = header_lines;
+        while let Some(text) = handle_io_error!(read_record_line(&mut reader)) {
+            line += 1;
+            let record = match vcf::record::Record::try_from_str(line_text(line, &text)?, &header) {
+                Ok(record) => record,
+                Err(err) => return Err(parse_error(line, err)),
+            };
+            write_converted(&mut output, &header, &record, converted)?;
+            converted += 1;
+        }
+        handle_io_error!(output.finish());
+    }
+
+    Ok(converted)
+}
+
+fn missing_or<T: ToString>(values: &[T], separator: &str) -> String {
+    if values.is_empty() {
+        ".".to_string()
+    } else {
+        values
+            .iter()
+            .map(|value| value.to_string())
+            .collect::<Vec<_>>()
+            .join(separator)
+    }
+}
+
+/// Renders a genotype value term back into its VCF text form.
+fn term_to_vcf_value(term: Term) -> Result<String, RustlerError> {
+    match term.get_type() {
+        TermType::Atom if is_missing_value(Some(term)) => Ok(".".to_string()),
+        TermType::Binary if is_missing_value(Some(term)) => Ok(".".to_string()),
+        TermType::Binary => term.decode(),
+        // Values come from `f32`s, so formatting them as such avoids spurious extra digits.
+        TermType::Number => match term.decode::<i64>() {
+            Ok(n) => Ok(n.to_string()),
+            Err(_err) => Ok((term.decode::<f64>()? as f32).to_string()),
+        },
+        TermType::EmptyList => Ok(".".to_string()),
+        TermType::List => {
+            let values = term
+                .decode::<Vec<Term>>()?
+                .into_iter()
+                .map(term_to_vcf_value)
+                .collect::<Result<Vec<_>, _>>()?;
+            Ok(values.join(","))
+        }
+        TermType::Map => {
+            let genotype: VcfGenotype = term.decode()?;
+            let separator = if genotype.phased { "|" } else { "/" };
+            let alleles: Vec<String> = genotype
+                .alleles
+                .iter()
+                .map(|allele| allele.map_or(".".to_string(), |allele| allele.to_string()))
+                .collect();
+            Ok(alleles.join(separator))
+        }
+        _ => Err(RustlerError::BadArg),
+    }
+}
+
+/// The entries of a record's `info`, whether it was read as a map or with `ordered_info: true`.
+fn info_entries(info: Term) -> Result<Vec<(Term, Term)>, RustlerError> {
+    if info.is_list() {
+        info.decode()
+    } else {
+        Ok(info.decode::<rustler::types::map::MapIterator>()?.collect())
+    }
+}
+
+/// Rebuilds the VCF line for a record struct and parses it against the writer's header.
+///
+/// Every INFO key must be declared in the writer's header; records using any other key are
+/// rejected rather than the header being extended behind the caller's back.
+fn struct_to_record(
+    env: Env,
+    header: &vcf::Header,
+    record: &VcfRecord,
+) -> Result<vcf::record::Record, String> {
+    let to_reason = |_err: RustlerError| "invalid field value".to_string();
+
+    let quality_score = match is_missing_value(Some(record.quality_score)) {
+        true => ".".to_string(),
+        false => (record.quality_score.decode::<f64>().map_err(to_reason)? as f32).to_string(),
+    };
+    let filters = match &record.filters {
+        VcfRecordFilters::None => ".".to_string(),
+        VcfRecordFilters::Pass => "PASS".to_string(),
+        VcfRecordFilters::Fail(filters) => filters.join(";"),
+    };
+    // Values read from a file are already `KEY=VALUE`, or `true` for flags; values added by the
+    // caller may be bare and get their key prepended, and a `false` flag is left out.
+    let mut info_values = Vec::new();
+    for (key, value) in info_entries(record.info).map_err(to_reason)? {
+        let key: String = key.decode().map_err(to_reason)?;
+        let value: String = match value.decode::<bool>() {
+            Ok(true) => key.clone(),
+            Ok(false) => continue,
+            Err(_err) => value.decode().map_err(to_reason)?,
+        };
+        let declared = key
+            .parse::<vcf::header::info::Key>()
+            .is_ok_and(|key| header.infos().contains_key(&key));
+        if !declared {
+            return Err(format!("INFO key {} is not declared in the header", key));
+        }
+        if value == key || value.starts_with(&format!("{}=", key)) {
+            info_values.push(value);
+        } else {
+            info_values.push(format!("{}={}", key, value));
+        }
+    }
+    let mut columns = vec![
+        record.chromosome.clone(),
+        record.position.to_string(),
+        missing_or(&record.ids, ";"),
+        record.reference_bases.clone(),
+        missing_or(&record.alternate_bases, ","),
+        quality_score,
+        filters,
+        missing_or(&info_values, ";"),
+    ];
+
+    // A writer without samples is sites-only, so FORMAT goes along with the genotypes.
+    if !record.format.is_empty() && !header.sample_names().is_empty() {
+        columns.push(record.format.join(":"));
+        for sample_name in header.sample_names() {
+            let sample = record
+                .genotypes
+                .map_get(sample_name.encode(env))
+                .map_err(|_err| format!("missing genotype for sample {}", sample_name))?;
+            let mut values = Vec::with_capacity(record.format.len());
+            for key in &record.format {
+                let value = match sample.map_get(key.encode(env)) {
+                    Ok(value) => term_to_vcf_value(value).map_err(to_reason)?,
+                    Err(_err) => ".".to_string(),
+                };
+                values.push(value);
+            }
+            columns.push(values.join(":"));
+        }
+    }
+
+    vcf::record::Record::try_from_str(&columns.join("\t"), header).map_err(|err| err.to_string())
+}
+
+#[rustler::nif]
+fn write_record(
+    env: Env,
+    writer: ResourceArc<VcfWriterHandle>,
+    record: VcfRecord,
+) -> Result<Atom, RustlerError> {
+    let record = match struct_to_record(env, &writer.header, &record) {
+        Ok(record) => record,
+        Err(reason) => {
+            return Err(RustlerError::Term(Box::new((
+                atoms::invalid_record(),
+                reason,
+            ))))
+        }
+    };
+    let mut stream = writer.open_stream()?;
+    handle_io_error!(stream.write_record(&record));
+    handle_io_error!(stream.get_mut().flush());
+    Ok(atoms::ok())
+}
+
+/// Writes records in order, stopping at the first one that cannot be serialized.
+///
+/// Records before the failing index have already been written when the error is returned.
+#[rustler::nif(schedule = "DirtyIo")]
+fn write_records(
+    env: Env,
+    writer: ResourceArc<VcfWriterHandle>,
+    records: Vec<VcfRecord>,
+) -> Result<Atom, RustlerError> {
+    let mut stream = writer.open_stream()?;
+    for (index, record) in records.iter().enumerate() {
+        match struct_to_record(env, &writer.header, record) {
+            Ok(record) => handle_io_error!(stream.write_record(&record)),
+            Err(reason) => {
+                handle_io_error!(stream.get_mut().flush());
+                return Err(RustlerError::Term(Box::new((
+                    atoms::invalid_record(),
+                    index,
+                    reason,
+                ))));
+            }
+        }
+    }
+    handle_io_error!(stream.get_mut().flush());
+    Ok(atoms::ok())
+}
+
+/// Flushes the output and, for bgzf, appends the end-of-file block. Later writes fail with
+/// `:closed`; closing again is a no-op.
+#[rustler::nif(schedule = "DirtyIo")]
+fn close_writer(writer: ResourceArc<VcfWriterHandle>) -> Result<Atom, RustlerError> {
+    let mut stream = lock(&writer.stream);
+    handle_io_error!(std::mem::replace(stream.get_mut(), VcfOutput::Closed).finish());
+    Ok(atoms::ok())
+}
+
+/// How the alternate alleles of a record move when they are sorted.
+struct AlleleOrder {
+    /// Old alternate allele index for each new alternate allele index.
+    alts: Vec<usize>,
+    /// New allele index (reference included) for each old allele index.
+    old_to_new: Vec<usize>,
+}
+
+impl AlleleOrder {
+    fn new(alternate_bases: &[String]) -> Self {
+        let mut alts: Vec<usize> = (0..alternate_bases.len()).collect();
+        alts.sort_by(|&a, &b| alternate_bases[a].cmp(&alternate_bases[b]));
+        let mut old_to_new = vec![0; alts.len() + 1];
+        for (new, &old) in alts.iter().enumerate() {
+            old_to_new[old + 1] = new + 1;
+        }
+        AlleleOrder { alts, old_to_new }
+    }
+
+    /// Reorders `Number=A` values; lists of any other length are left alone.
+    fn per_alt<T: Clone>(&self, values: &[T]) -> Option<Vec<T>> {
+        if values.len() != self.alts.len() {
+            return None;
+        }
+        Some(self.alts.iter().map(|&old| values[old].clone()).collect())
+    }
+
+    /// Reorders `Number=R` values, which lead with the reference allele.
+    fn per_allele<T: Copy>(&self, values: &[T]) -> Option<Vec<T>> {
+        let (reference, alts) = values.split_first()?;
+        let mut reordered = vec![*reference];
+        reordered.extend(self.per_alt(alts)?);
+        Some(reordered)
+    }
+
+    /// Reorders `Number=G` values for haploid or diploid calls.
+    fn per_genotype<T: Copy>(&self, values: &[T]) -> Option<Vec<T>> {
+        let allele_count = self.old_to_new.len();
+        if values.len() == allele_count {
+            return self.per_allele(values);
+        }
+        if values.len() != allele_count * (allele_count + 1) / 2 {
+            return None;
+        }
+
+        let mut new_to_old = vec![0; allele_count];
+        for (old, &new) in self.old_to_new.iter().enumerate() {
+            new_to_old[new] = old;
+        }
+        // Diploid genotypes `j/k` with `j <= k` are ordered by `k * (k + 1) / 2 + j`.
+        let mut reordered = Vec::with_capacity(values.len());
+        for k in 0..allele_count {
+            for j in 0..=k {
+                let (a, b) = (new_to_old[j], new_to_old[k]);
+                let (j, k) = (a.min(b), a.max(b));
+                reordered.push(values[k * (k + 1) / 2 + j]);
+            }
+        }
+        Some(reordered)
+    }
+
+    fn genotype(&self, mut genotype: VcfGenotype) -> VcfGenotype {
+        for allele in genotype.alleles.iter_mut().flatten() {
+            *allele = self.old_to_new.get(*allele).copied().unwrap_or(*allele);
+        }
+        if !genotype.phased {
+            // Unphased calls are unordered; missing alleles sort last.
+            genotype
+                .alleles
+                .sort_by_key(|allele| (allele.is_none(), *allele));
+        }
+        genotype
+    }
+}
+
+fn reorder_list<'a>(
+    env: Env<'a>,
+    value: Term<'a>,
+    reorder: impl Fn(&[Term<'a>]) -> Option<Vec<Term<'a>>>,
+) -> Term<'a> {
+    match value.decode::<Vec<Term>>() {
+        Ok(values) => reorder(&values).map_or(value, |values| values.encode(env)),
+        Err(_err) => value,
+    }
+}
+
+/// Reorders the comma-separated values of a raw `KEY=VALUE` INFO entry.
+fn reorder_info_value(order: &AlleleOrder, raw: &str) -> String {
+    let (key, values) = match raw.split_once('=') {
+        Some(pair) => pair,
+        None => return raw.to_string(),
+    };
+    let values: Vec<&str> = values.split(',').collect();
+    let reordered = match key {
+        "AC" | "AF" => order.per_alt(&values),
+        "AD" | "ADF" | "ADR" => order.per_allele(&values),
+        _ => None,
+    };
+    match reordered {
+        Some(values) => format!("{}={}", key, values.join(",")),
+        None => raw.to_string(),
+    }
+}
+
+fn is_missing_value(value: Option<Term>) -> bool {
+    value.is_none_or(|value| match value.get_type() {
+        TermType::Atom => rustler::types::atom::nil() == value || atoms::missing() == value,
+        TermType::Binary => value.decode::<&str>().is_ok_and(str::is_empty),
+        _ => false,
+    })
+}
+
+/// Canonicalizes a record so that semantically equal records compare equal.
+///
+/// Bases are uppercased, alternate alleles are sorted with every allele-indexed value
+/// (`GT`, `AC`/`AF`, `AD`/`ADF`/`ADR`, `PL`/`GL`/`GP`) renumbered to match, unphased calls are
+/// sorted, and trailing FORMAT keys that are missing in every sample are dropped.
+#[rustler::nif]
+fn normalize_record<'a>(
+    env: Env<'a>,
+    record: VcfRecord<'a>,
+) -> Result<VcfRecord<'a>, RustlerError> {
+    let uppercase = |bases: &String| {
+        if bases.chars().all(|c| c.is_ascii_alphabetic()) {
+            bases.to_ascii_uppercase()
+        } else {
+            bases.clone()
+        }
+    };
+    let reference_bases = uppercase(&record.reference_bases);
+    let alternate_bases: Vec<String> = record.alternate_bases.iter().map(uppercase).collect();
+    let order = AlleleOrder::new(&alternate_bases);
+    // Unresolved alleles are copies of `alternate_bases`, so they take its uppercasing.
+    let resolved_alternate_bases: Vec<Term> = record
+        .resolved_alternate_bases
+        .iter()
+        .zip(&alternate_bases)
+        .map(|(&resolved, bases)| match resolved.get_type() {
+            TermType::Binary => bases.encode(env),
+            _ => resolved,
+        })
+        .collect();
+
+    let mut info_pairs = Vec::new();
+    for (key, value) in info_entries(record.info)? {
+        match value.decode::<String>() {
+            Ok(raw) => info_pairs.push((key, reorder_info_value(&order, &raw).encode(env))),
+            // Flags have no values to reorder.
+            Err(_err) => info_pairs.push((key, value)),
+        }
+    }
+
+    let mut reserved_info_pairs = Vec::new();
+    for (key, value) in record
+        .reserved_info
+        .decode::<rustler::types::map::MapIterator>()?
+    {
+        let key_atom: Atom = key.decode()?;
+        let value = if key_atom == atoms::allele_count()
+            || key_atom == atoms::allele_frequencies()
+            || key_atom == atoms::cigar()
+        {
+            reorder_list(env, value, |values| order.per_alt(values))
+        } else if key_atom == atoms::total_read_depths()
+            || key_atom == atoms::forward_strand_read_depths()
+            || key_atom == atoms::reverse_strand_read_depths()
+        {
+            reorder_list(env, value, |values| order.per_allele(values))
+        } else {
+            value
+        };
+        reserved_info_pairs.push((key, value));
+    }
+
+    let mut allele_info_pairs = Vec::new();
+    for (key, alleles) in record
+        .allele_info
+        .decode::<rustler::types::map::MapIterator>()?
+    {
+        let mut allele_pairs = Vec::new();
+        for (allele, value) in alleles.decode::<rustler::types::map::MapIterator>()? {
+            allele_pairs.push((uppercase(&allele.decode()?), value));
+        }
+        allele_info_pairs.push((key, Term::map_from_pairs(env, &allele_pairs)?));
+    }
+
+    let samples: Vec<(Term, Term)> = record
+        .genotypes
+        .decode::<rustler::types::map::MapIterator>()?
+        .collect();
+    let mut format = record.format.clone();
+    while let Some(key) = format.last() {
+        let key_term = key.encode(env);
+        let all_missing = samples
+            .iter()
+            .all(|(_sample, fields)| is_missing_value(fields.map_get(key_term).ok()));
+        if key == "GT" || !all_missing {
+            break;
+        }
+        format.pop();
+    }
+
+    let mut genotypes_pairs = Vec::with_capacity(samples.len());
+    for (sample, fields) in samples {
+        // A sample whose column was missing from the line stays nil.
+        if fields.get_type() == TermType::Atom {
+            genotypes_pairs.push((sample, fields));
+            continue;
+        }
+        let mut field_pairs = Vec::new();
+        for (key, value) in fields.decode::<rustler::types::map::MapIterator>()? {
+            let key_string: String = key.decode()?;
+            if !format.contains(&key_string) {
+                continue;
+            }
+            let value = match key_string.as_str() {
+                "GT" => match value.decode::<VcfGenotype>() {
+                    Ok(genotype) => order.genotype(genotype).encode(env),
+                    Err(_err) => value,
+                },
+                "AD" | "ADF" | "ADR" => reorder_list(env, value, |values| order.per_allele(values)),
+                "PL" | "GL" | "GP" => reorder_list(env, value, |values| order.per_genotype(values)),
+                _ => value,
+            };
+            field_pairs.push((key, value));
+        }
+        genotypes_pairs.push((sample, Term::map_from_pairs(env, &field_pairs)?));
+    }
+
+    Ok(VcfRecord {
+        reference_bases,
+        alt_kinds: order.per_alt(&record.alt_kinds).unwrap_or(record.alt_kinds),
+        variant_type: order
+            .per_alt(&record.variant_type)
+            .unwrap_or(record.variant_type),
+        breakends: order.per_alt(&record.breakends).unwrap_or(record.breakends),
+        alternate_bases: order
+            .alts
+            .iter()
+            .map(|&old| alternate_bases[old].clone())
+            .collect(),
+        resolved_alternate_bases: order
+            .per_alt(&resolved_alternate_bases)
+            .unwrap_or(resolved_alternate_bases),
+        info: if record.info.is_list() {
+            info_pairs.encode(env)
+        } else {
+            Term::map_from_pairs(env, &info_pairs)?
+        },
+        reserved_info: Term::map_from_pairs(env, &reserved_info_pairs)?,
+        allele_info: Term::map_from_pairs(env, &allele_info_pairs)?,
+        format,
+        genotypes: Term::map_from_pairs(env, &genotypes_pairs)?,
+        ..record
+    })
+}
+
 rustler::init!(
     "Elixir.Noodlex.Vcf",
-    [get_handle, get_header, get_record, get_records],
+    [
+        get_handle,
+        get_handle_with_opts,
+        get_handle_mmap,
+        get_handle_from_binary,
+        get_handle_from_stdin,
+        handle_info,
+        rewind,
+        reload_header,
+        set_parse_header,
+        get_header,
+        read_header_only,
+        valid_vcf,
+        merge_headers,
+        describe_info_key,
+        sv_info_definitions,
+        info_keys,
+        format_keys,
+        get_record,
+        get_record_with_opts,
+        next_record,
+        get_raw_record,
+        read_raw_records,
+        get_sample_genotype,
+        at_eof,
+        estimate_remaining,
+        skip_records,
+        get_records_range,
+        get_records_min_qual,
+        get_records_min_qual_with_opts,
+        peek_first_record,
+        get_records,
+        get_records_with_opts,
+        get_records_with_cursor,
+        get_records_for_contig,
+        get_records_in_range,
+        get_records_lenient,
+        get_all_records,
+        build_index,
+        query,
+        region_summary,
+        count_records,
+        tail,
+        check_sorted,
+        sorted,
+        observed_contigs,
+        records_per_contig,
+        filter_histogram,
+        genotype_concordance,
+        phase_sets,
+        read_index,
+        get_writer,
+        get_writer_with_opts,
+        open_writer_append,
+        write_record,
+        write_records,
+        close_writer,
+        copy_records,
+        export_json,
+        convert,
+        normalize_record
+    ],
     load = load
 );
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lock_takes_over_a_poisoned_mutex() {
+        let mutex = sync::Arc::new(sync::Mutex::new(0));
+        let poisoner = sync::Arc::clone(&mutex);
+        let result = std::thread::spawn(move || {
+            let mut guard = poisoner.lock().unwrap();
+            *guard += 1;
+            panic!("poisoning the lock");
+        })
+        .join();
+
+        assert!(result.is_err());
+        assert!(mutex.is_poisoned());
+        assert_eq!(*lock(&mutex), 1);
+    }
+
+    const BGZF_FIXTURE: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/../../priv/test_bgzf.vcf.gz");
+
+    /// Indexes the bgzf fixture in memory, the way `build_index` would.
+    fn fixture_index(header: &vcf::Header) -> tabix::Index {
+        let mut reader = vcf::Reader::new(bgzf::Reader::new(File::open(BGZF_FIXTURE).unwrap()));
+        read_raw_header(&mut reader).unwrap();
+        let mut indexer = tabix::Index::indexer();
+        indexer.set_header(tabix::index::header::Builder::vcf().build());
+        let mut start_position = reader.virtual_position();
+        while let Some(Ok(text)) = read_record_line(&mut reader).unwrap() {
+            let end_position = reader.virtual_position();
+            let record = vcf::record::Record::try_from_str(&text, header).unwrap();
+            indexer.add_record(
+                &record.chromosome().to_string(),
+                index_position(record.position()),
+                index_position(record.end().unwrap()),
+                Chunk::new(start_position, end_position),
+            );
+            start_position = end_position;
+        }
+        indexer.build()
+    }
+
+    /// A handle over the bgzf fixture, built without atoms so it needs no running VM.
+    fn fixture_handle() -> VcfHandle {
+        let file = File::open(BGZF_FIXTURE).unwrap();
+        let mut reader = vcf::Reader::new(VcfInput::Bgzf(bgzf::Reader::new(file)));
+        let (raw_header, header_lines) = read_raw_header(&mut reader).unwrap();
+        let header = raw_header.parse().unwrap();
+        let index = fixture_index(&header);
+        VcfHandle {
+            header: sync::RwLock::new(header),
+            stream: sync::Mutex::new(reader),
+            line: AtomicUsize::new(header_lines),
+            line_known: AtomicBool::new(true),
+            header_lines: AtomicUsize::new(header_lines),
+            data_offset: AtomicU64::new(0),
+            path: Some(BGZF_FIXTURE.to_string()),
+            bgzf: true,
+            compression: Compression::Bgzf,
+            index: sync::Mutex::new(Some(sync::Arc::new(index))),
+            missing: MissingValue::Nil,
+            max_field_len: None,
+        }
+    }
+
+    #[test]
+    fn region_reads_never_take_the_stream_lock() {
+        let handle = sync::Arc::new(fixture_handle());
+        let querier = sync::Arc::clone(&handle);
+        let (sender, receiver) = sync::mpsc::channel();
+
+        // Held for the whole test, so a region read that took the stream lock would never finish.
+        let _stream = lock(&handle.stream);
+        std::thread::spawn(move || {
+            let header = read_lock(&querier.header);
+            let region = noodles_core::Region::new("chr1", ..);
+            let mut count = 0;
+            let result = read_region(&querier, BGZF_FIXTURE, &header, &region, |_record| {
+                count += 1;
+                Ok::<(), ()>(())
+            });
+            let _ = sender.send(result.ok().and_then(Result::ok).map(|()| count));
+        });
+
+        let count = receiver.recv_timeout(std::time::Duration::from_secs(30));
+        assert_eq!(count, Ok(Some(135)));
+    }
+
+    #[test]
+    fn write_lock_takes_over_a_poisoned_rwlock() {
+        let rwlock = sync::Arc::new(sync::RwLock::new(0));
+        let poisoner = sync::Arc::clone(&rwlock);
+        let result = std::thread::spawn(move || {
+            let mut guard = poisoner.write().unwrap();
+            *guard += 1;
+            panic!("poisoning the lock");
+        })
+        .join();
+
+        assert!(result.is_err());
+        assert!(rwlock.is_poisoned());
+        assert_eq!(*read_lock(&rwlock), 1);
+        *write_lock(&rwlock) += 1;
+        assert_eq!(*read_lock(&rwlock), 2);
+    }
+}
@@ -4,6 +4,60 @@ defmodule Noodlex.VcfTest do
 
   @test_vcf_path :noodlex |> :code.priv_dir() |> Path.join("test.vcf")
   @test_for_batched_vcf_path :noodlex |> :code.priv_dir() |> Path.join("test_for_batched.vcf")
+  @sites_only_vcf_path :noodlex |> :code.priv_dir() |> Path.join("sites_only.vcf")
+  @header_other_vcf_path :noodlex |> :code.priv_dir() |> Path.join("header_other.vcf")
+  @malformed_vcf_path :noodlex |> :code.priv_dir() |> Path.join("malformed.vcf")
+  @multiallelic_vcf_path :noodlex |> :code.priv_dir() |> Path.join("multiallelic.vcf")
+  @fullgenome_vcf_path :noodlex |> :code.priv_dir() |> Path.join("fullgenome.vcf")
+  @trio_vcf_path :noodlex |> :code.priv_dir() |> Path.join("trio.vcf")
+  @typed_genotypes_vcf_path :noodlex |> :code.priv_dir() |> Path.join("typed_genotypes.vcf")
+  @alt_kinds_vcf_path :noodlex |> :code.priv_dir() |> Path.join("alt_kinds.vcf")
+  @no_records_vcf_path :noodlex |> :code.priv_dir() |> Path.join("no_records.vcf")
+  @stats_vcf_path :noodlex |> :code.priv_dir() |> Path.join("stats.vcf")
+  @test_gzip_vcf_path :noodlex |> :code.priv_dir() |> Path.join("test_gzip.vcf.gz")
+  @test_bgzf_vcf_path :noodlex |> :code.priv_dir() |> Path.join("test_bgzf.vcf.gz")
+  @v4_2_vcf_path :noodlex |> :code.priv_dir() |> Path.join("v4_2.vcf")
+  @v4_4_vcf_path :noodlex |> :code.priv_dir() |> Path.join("v4_4.vcf")
+  @one_bad_record_vcf_path :noodlex |> :code.priv_dir() |> Path.join("one_bad_record.vcf")
+  @ploidy_vcf_path :noodlex |> :code.priv_dir() |> Path.join("ploidy.vcf")
+  @normalize_a_vcf_path :noodlex |> :code.priv_dir() |> Path.join("normalize_a.vcf")
+  @normalize_b_vcf_path :noodlex |> :code.priv_dir() |> Path.join("normalize_b.vcf")
+  @variant_types_vcf_path :noodlex |> :code.priv_dir() |> Path.join("variant_types.vcf")
+  @ids_vcf_path :noodlex |> :code.priv_dir() |> Path.join("ids.vcf")
+  @manta_sv_vcf_path :noodlex |> :code.priv_dir() |> Path.join("manta_sv.vcf")
+  @stats_stripped_vcf_path :noodlex |> :code.priv_dir() |> Path.join("stats_stripped.vcf")
+  @af_vcf_path :noodlex |> :code.priv_dir() |> Path.join("af.vcf")
+  @af_stripped_vcf_path :noodlex |> :code.priv_dir() |> Path.join("af_stripped.vcf")
+  @bad_header_vcf_path :noodlex |> :code.priv_dir() |> Path.join("bad_header.vcf")
+  @breakends_vcf_path :noodlex |> :code.priv_dir() |> Path.join("breakends.vcf")
+  @missing_samples_vcf_path :noodlex |> :code.priv_dir() |> Path.join("missing_samples.vcf")
+  @all_missing_vcf_path :noodlex |> :code.priv_dir() |> Path.join("all_missing.vcf")
+  @filters_vcf_path :noodlex |> :code.priv_dir() |> Path.join("filters.vcf")
+  @cigar_vcf_path :noodlex |> :code.priv_dir() |> Path.join("cigar.vcf")
+  @long_info_key_vcf_path :noodlex |> :code.priv_dir() |> Path.join("long_info_key.vcf")
+  @unsorted_vcf_path :noodlex |> :code.priv_dir() |> Path.join("unsorted.vcf")
+  @merge_a_vcf_path :noodlex |> :code.priv_dir() |> Path.join("merge_a.vcf")
+  @merge_b_vcf_path :noodlex |> :code.priv_dir() |> Path.join("merge_b.vcf")
+  @merge_conflict_vcf_path :noodlex |> :code.priv_dir() |> Path.join("merge_conflict.vcf")
+  @allele_info_vcf_path :noodlex |> :code.priv_dir() |> Path.join("allele_info.vcf")
+  @annotate_vcf_path :noodlex |> :code.priv_dir() |> Path.join("annotate.vcf")
+  @observed_contigs_vcf_path :noodlex |> :code.priv_dir() |> Path.join("observed_contigs.vcf")
+  @interleaved_bad_vcf_path :noodlex |> :code.priv_dir() |> Path.join("interleaved_bad.vcf")
+  @format_order_vcf_path :noodlex |> :code.priv_dir() |> Path.join("format_order.vcf")
+  @colon_contig_vcf_path :noodlex |> :code.priv_dir() |> Path.join("colon_contig.vcf")
+  @filter_mix_vcf_path :noodlex |> :code.priv_dir() |> Path.join("filter_mix.vcf")
+  @confidence_vcf_path :noodlex |> :code.priv_dir() |> Path.join("confidence_intervals.vcf")
+  @symbolic_alts_vcf_path :noodlex |> :code.priv_dir() |> Path.join("symbolic_alts.vcf")
+  @undeclared_info_vcf_path :noodlex |> :code.priv_dir() |> Path.join("undeclared_info.vcf")
+  @concordance_vcf_path :noodlex |> :code.priv_dir() |> Path.join("concordance.vcf")
+  @info_flags_vcf_path :noodlex |> :code.priv_dir() |> Path.join("info_flags.vcf")
+  @mobile_elements_vcf_path :noodlex |> :code.priv_dir() |> Path.join("mobile_elements.vcf")
+  @phased_vcf_path :noodlex |> :code.priv_dir() |> Path.join("phased.vcf")
+  @allele_depths_vcf_path :noodlex |> :code.priv_dir() |> Path.join("allele_depths.vcf")
+  @mixed_qual_vcf_path :noodlex |> :code.priv_dir() |> Path.join("mixed_qual.vcf")
+  @copy_number_vcf_path :noodlex |> :code.priv_dir() |> Path.join("copy_number.vcf")
+  @ancestral_allele_vcf_path :noodlex |> :code.priv_dir() |> Path.join("ancestral_allele.vcf")
+  @strand_depths_vcf_path :noodlex |> :code.priv_dir() |> Path.join("strand_depths.vcf")
 
   test "reads correct amount of records from `test.vcf`" do
     handle = Noodlex.Vcf.get_handle(@test_vcf_path)
@@ -46,6 +100,1800 @@ defmodule Noodlex.VcfTest do
     IO.puts("Time elapsed for batched read: #{diff_time} ms")
   end
 
+  test "describes INFO keys declared in the header" do
+    handle = Noodlex.Vcf.get_handle(@test_vcf_path)
+
+    assert %Noodlex.Vcf.Header.Info{id: :AF, type_: :float, number: :alternate_alleles} =
+             Noodlex.Vcf.describe_info_key(handle, "AF")
+
+    assert %Noodlex.Vcf.Header.Info{type_: :integer, number: 1} =
+             Noodlex.Vcf.describe_info_key(handle, "DP")
+
+    assert {:error, :not_found} = Noodlex.Vcf.describe_info_key(handle, "NOPE")
+  end
+
+  test "reads sites-only records with empty format and genotypes" do
+    handle = Noodlex.Vcf.get_handle(@sites_only_vcf_path)
+
+    record = Noodlex.Vcf.get_record(handle)
+    assert record.position == 10177
+    assert record.format == []
+    assert record.genotypes == %{}
+
+    records = Noodlex.Vcf.get_records(handle, 10)
+    assert length(records) == 2
+    assert Enum.all?(records, &(&1.format == [] and &1.genotypes == %{}))
+  end
+
+  test "parallel batch parsing in `get_records` matches sequential `get_record`" do
+    sequential = @test_vcf_path |> Noodlex.Vcf.get_handle() |> read_all_records()
+
+    handle = Noodlex.Vcf.get_handle(@test_vcf_path)
+
+    parallel =
+      fn -> Noodlex.Vcf.get_records(handle, 1_000) end
+      |> Stream.repeatedly()
+      |> Enum.take_while(&(&1 != []))
+      |> Enum.concat()
+
+    assert length(parallel) == 2588
+    assert parallel == sequential
+  end
+
+  test "exposes generic header lines under `other`" do
+    header = @header_other_vcf_path |> Noodlex.Vcf.get_handle() |> Noodlex.Vcf.get_header()
+
+    assert header.other["reference"] == ["file:///seq/references/GRCh38.fasta"]
+    assert header.other["source"] == ["caller_a", "caller_b"]
+    assert header.other["fileDate"] == ["20221210"]
+    assert header.other["assembly"] == ["GRCh38"]
+  end
+
+  test "a malformed record does not poison the records after it" do
+    handle = Noodlex.Vcf.get_handle(@malformed_vcf_path)
+
+    assert %Noodlex.Vcf.Record{position: 100} = Noodlex.Vcf.get_record(handle)
+    assert {:error, _reason} = Noodlex.Vcf.get_record(handle)
+    assert %Noodlex.Vcf.Record{position: 300} = Noodlex.Vcf.get_record(handle)
+
+    handle = Noodlex.Vcf.get_handle(@malformed_vcf_path)
+
+    assert [%Noodlex.Vcf.Record{position: 100}] = Noodlex.Vcf.get_records(handle, 1)
+    assert {:error, _reason} = Noodlex.Vcf.get_records(handle, 1)
+    assert [%Noodlex.Vcf.Record{position: 300}] = Noodlex.Vcf.get_records(handle, 1)
+  end
+
+  test "`get_all_records` reads every record up to the end of the file" do
+    expected = @test_vcf_path |> Noodlex.Vcf.get_handle() |> read_all_records()
+
+    handle = Noodlex.Vcf.get_handle(@test_vcf_path)
+    records = Noodlex.Vcf.get_all_records(handle)
+
+    assert records == expected
+    assert Noodlex.Vcf.get_all_records(handle) == []
+    assert {:error, :end_of_file} = Noodlex.Vcf.get_record(handle)
+
+    assert {:error, _reason} =
+             @malformed_vcf_path |> Noodlex.Vcf.get_handle() |> Noodlex.Vcf.get_all_records()
+  end
+
+  test "returns alternate bases as a list of alleles" do
+    handle = Noodlex.Vcf.get_handle(@multiallelic_vcf_path)
+
+    assert %Noodlex.Vcf.Record{reference_bases: "A", alternate_bases: ["C"]} =
+             Noodlex.Vcf.get_record(handle)
+
+    assert %Noodlex.Vcf.Record{reference_bases: "G", alternate_bases: ["A", "T", "GT"]} =
+             Noodlex.Vcf.get_record(handle)
+
+    assert %Noodlex.Vcf.Record{alternate_bases: []} = Noodlex.Vcf.get_record(handle)
+  end
+
+  test "reads records piped through standard input" do
+    [noodlex_ebin, rustler_ebin] =
+      Enum.map([Noodlex.Vcf, Rustler], &(&1 |> :code.which() |> Path.dirname()))
+
+    script = """
+    handle = Noodlex.Vcf.get_handle_from_stdin()
+    header = Noodlex.Vcf.get_header(handle)
+    records = Noodlex.Vcf.get_all_records(handle)
+    IO.write("\#{header.fileformat.major}.\#{header.fileformat.minor} \#{length(records)}")
+    """
+
+    # `-noinput` keeps the VM's own IO server from competing with the NIF for stdin.
+    command = ~s(cat "$1" | elixir --erl "-noinput" -pa "$2" -pa "$3" -e "$4")
+
+    args = ["-c", command, "sh", @test_vcf_path, noodlex_ebin, rustler_ebin, script]
+    assert {"4.1 2588", 0} = System.cmd("sh", args)
+  end
+
+  test "keys genotypes by sample and reads a single sample's genotype" do
+    handle = Noodlex.Vcf.get_handle(@fullgenome_vcf_path)
+
+    record = Noodlex.Vcf.get_record(handle)
+    assert Map.keys(record.genotypes) |> Enum.sort() == ["108", "139", "159", "88"]
+    assert %{"GQ" => 40, "DP" => 8} = record.genotypes["139"]
+
+    assert %{"GT" => %Noodlex.Vcf.Record.Genotype{}, "GQ" => 36, "DP" => 5} =
+             Noodlex.Vcf.get_sample_genotype(handle, "108")
+
+    assert {:error, {:not_found, "NA12878"}} = Noodlex.Vcf.get_sample_genotype(handle, "NA12878")
+    assert %Noodlex.Vcf.Record{position: 3633} = Noodlex.Vcf.get_record(handle)
+
+    handle = Noodlex.Vcf.get_handle(@fullgenome_vcf_path, max_field_len: 3)
+
+    assert {:error, {:field_too_long, _line, _column}} =
+             Noodlex.Vcf.get_sample_genotype(handle, "108")
+  end
+
+  test "exposes PEDIGREE and SAMPLE header records" do
+    header = @trio_vcf_path |> Noodlex.Vcf.get_handle() |> Noodlex.Vcf.get_header()
+
+    assert header.pedigrees == %{"child" => %{"Father" => "father", "Mother" => "mother"}}
+
+    assert header.samples_meta == %{
+             "child" => %{"Assay" => "WholeGenome", "Ethnicity" => "AFR"},
+             "father" => %{"Assay" => "WholeGenome", "Ethnicity" => "AFR"},
+             "mother" => %{"Assay" => "Exome", "Ethnicity" => "AFR"}
+           }
+
+    header = @test_vcf_path |> Noodlex.Vcf.get_handle() |> Noodlex.Vcf.get_header()
+    assert header.pedigrees == %{}
+    assert header.samples_meta == %{}
+  end
+
+  test "rewinds and reloads the header of an open handle" do
+    handle = Noodlex.Vcf.get_handle(@test_vcf_path)
+    header = Noodlex.Vcf.get_header(handle)
+    [first | _rest] = Noodlex.Vcf.get_records(handle, 10)
+
+    assert :ok = Noodlex.Vcf.rewind(handle)
+    assert Noodlex.Vcf.get_record(handle) == first
+
+    assert :ok = Noodlex.Vcf.reload_header(handle)
+    assert Noodlex.Vcf.get_header(handle) == header
+    assert Noodlex.Vcf.get_record(handle) == first
+  end
+
+  test "types genotype values using the FORMAT header definitions" do
+    record = @typed_genotypes_vcf_path |> Noodlex.Vcf.get_handle() |> Noodlex.Vcf.get_record()
+
+    assert record.genotypes["sample1"] == %{
+             "GT" => %Noodlex.Vcf.Record.Genotype{alleles: [0, 1], phased: false},
+             "AD" => [12, 8],
+             "DP" => 20,
+             "GQ" => 99,
+             "GL" => [-10.5, -0.5, -20.0],
+             "PL" => [105, 5, 200]
+           }
+
+    assert record.genotypes["sample2"] == %{
+             "GT" => %Noodlex.Vcf.Record.Genotype{alleles: [nil, nil], phased: false},
+             "AD" => nil,
+             "DP" => nil,
+             "GQ" => nil,
+             "GL" => nil,
+             "PL" => nil
+           }
+
+    handle = Noodlex.Vcf.get_handle(@fullgenome_vcf_path)
+    fullgenome_record = Noodlex.Vcf.get_record(handle)
+
+    assert %Noodlex.Vcf.Record.Genotype{alleles: [0, 0], phased: true} =
+             fullgenome_record.genotypes["88"]["GT"]
+  end
+
+  test "skips records without building them" do
+    expected =
+      @test_vcf_path |> Noodlex.Vcf.get_handle() |> Noodlex.Vcf.get_records(6) |> List.last()
+
+    handle = Noodlex.Vcf.get_handle(@test_vcf_path)
+    assert Noodlex.Vcf.skip_records(handle, 5) == 5
+    assert Noodlex.Vcf.get_record(handle) == expected
+
+    assert Noodlex.Vcf.skip_records(handle, 10_000) == 2588 - 6
+    assert Noodlex.Vcf.skip_records(handle, 1) == 0
+  end
+
+  test "classifies each alternate allele by kind" do
+    records = @alt_kinds_vcf_path |> Noodlex.Vcf.get_handle() |> Noodlex.Vcf.get_all_records()
+
+    assert Enum.map(records, & &1.alt_kinds) == [
+             [:snv],
+             [:symbolic],
+             [:breakend],
+             [:other, :other]
+           ]
+  end
+
+  test "handles a valid header with no records" do
+    handle = Noodlex.Vcf.get_handle(@no_records_vcf_path)
+
+    assert %Noodlex.Vcf.Header{fileformat: %{major: 4, minor: 3}} = Noodlex.Vcf.get_header(handle)
+    assert {:error, :end_of_file} = Noodlex.Vcf.get_record(handle)
+    assert [] = Noodlex.Vcf.get_records(handle, 10)
+    assert [] = Noodlex.Vcf.get_all_records(handle)
+    assert Noodlex.Vcf.skip_records(handle, 1) == 0
+  end
+
+  test "computes NS, AN and AC from genotypes when INFO lacks them" do
+    handle = Noodlex.Vcf.get_handle(@stats_vcf_path)
+    stripped_handle = Noodlex.Vcf.get_handle(@stats_stripped_vcf_path)
+
+    Enum.each(1..3, fn _i ->
+      expected = Noodlex.Vcf.get_record(handle)
+      computed = Noodlex.Vcf.get_record(stripped_handle, compute_stats: true)
+
+      assert computed.reserved_info == expected.reserved_info
+    end)
+
+    stripped_handle = Noodlex.Vcf.get_handle(@stats_stripped_vcf_path)
+    assert %{reserved_info: reserved_info} = Noodlex.Vcf.get_record(stripped_handle)
+    assert reserved_info == %{}
+
+    assert %{samples_with_data_count: 3, total_allele_count: 6, allele_count: [1, 2]} ==
+             Noodlex.Vcf.get_record(stripped_handle, compute_stats: true).reserved_info
+  end
+
+  test "reads identical records through the memory-mapped backend" do
+    buffered = @test_vcf_path |> Noodlex.Vcf.get_handle() |> Noodlex.Vcf.get_all_records()
+    mmap_handle = Noodlex.Vcf.get_handle_mmap(@test_vcf_path)
+
+    assert Noodlex.Vcf.get_header(mmap_handle) ==
+             @test_vcf_path |> Noodlex.Vcf.get_handle() |> Noodlex.Vcf.get_header()
+
+    assert Noodlex.Vcf.get_all_records(mmap_handle) == buffered
+    assert :ok = Noodlex.Vcf.rewind(mmap_handle)
+    assert Noodlex.Vcf.get_record(mmap_handle) == hd(buffered)
+    assert {:error, :not_found} = Noodlex.Vcf.get_handle_mmap("does_not_exist.vcf")
+  end
+
+  test "reports parse failures with the line they came from" do
+    handle = Noodlex.Vcf.get_handle(@malformed_vcf_path)
+
+    assert %Noodlex.Vcf.Record{position: 100} = Noodlex.Vcf.get_record(handle)
+    assert {:error, {:parse, 6, reason}} = Noodlex.Vcf.get_record(handle)
+    assert is_binary(reason)
+
+    handle = Noodlex.Vcf.get_handle(@malformed_vcf_path)
+    assert {:error, {:parse, 6, ^reason}} = Noodlex.Vcf.get_records(handle, 3)
+  end
+
+  test "parse failures in batched reads print nothing to stdout" do
+    [noodlex_ebin, rustler_ebin] =
+      Enum.map([Noodlex.Vcf, Rustler], &(&1 |> :code.which() |> Path.dirname()))
+
+    script = """
+    [path] = System.argv()
+    handle = Noodlex.Vcf.get_handle(path)
+    {:error, {:parse, line, _reason}} = Noodlex.Vcf.get_records(handle, 3)
+    IO.write("line \#{line}")
+    """
+
+    args = ["-pa", noodlex_ebin, "-pa", rustler_ebin, "-e", script, @malformed_vcf_path]
+    assert {"line 6", 0} = System.cmd("elixir", args)
+  end
+
+  test "tags each record with the physical line it was read from" do
+    header_lines = @test_vcf_path |> File.stream!() |> Enum.count(&String.starts_with?(&1, "#"))
+    handle = Noodlex.Vcf.get_handle(@test_vcf_path)
+
+    assert %Noodlex.Vcf.Record{line: first_line} = Noodlex.Vcf.get_record(handle)
+    assert first_line == header_lines + 1
+    assert [%{line: line}, _record] = Noodlex.Vcf.get_records(handle, 2)
+    assert line == header_lines + 2
+    assert 1 = Noodlex.Vcf.skip_records(handle, 1)
+    assert %Noodlex.Vcf.Record{line: line} = Noodlex.Vcf.get_record(handle)
+    assert line == header_lines + 5
+
+    assert :ok = Noodlex.Vcf.rewind(handle)
+    assert %Noodlex.Vcf.Record{line: ^first_line} = Noodlex.Vcf.get_record(handle)
+  end
+
+  @tag :tmp_dir
+  test "writes a batch of records that read back unchanged", %{tmp_dir: tmp_dir} do
+    path = Path.join(tmp_dir, "written.vcf")
+    handle = Noodlex.Vcf.get_handle(@test_vcf_path)
+    records = handle |> Noodlex.Vcf.get_all_records() |> Stream.cycle() |> Enum.take(10_000)
+
+    writer = Noodlex.Vcf.get_writer(path, handle)
+    assert :ok = Noodlex.Vcf.write_records(writer, records)
+
+    written = path |> Noodlex.Vcf.get_handle() |> Noodlex.Vcf.get_all_records()
+    assert length(written) == 10_000
+    assert Enum.map(written, &Map.delete(&1, :line)) == Enum.map(records, &Map.delete(&1, :line))
+
+    writer = Noodlex.Vcf.get_writer(path, handle)
+    invalid = %{hd(records) | reference_bases: ""}
+
+    assert {:error, {:invalid_record, 1, _reason}} =
+             Noodlex.Vcf.write_records(writer, [hd(records), invalid, hd(records)])
+
+    assert [%Noodlex.Vcf.Record{}] =
+             path |> Noodlex.Vcf.get_handle() |> Noodlex.Vcf.get_all_records()
+  end
+
+  test "reads plain gzip and bgzf compressed files transparently" do
+    expected = @test_vcf_path |> Noodlex.Vcf.get_handle() |> Noodlex.Vcf.get_all_records()
+
+    gzip_handle = Noodlex.Vcf.get_handle(@test_gzip_vcf_path)
+    assert Noodlex.Vcf.get_all_records(gzip_handle) == expected
+    assert {:error, :not_seekable} = Noodlex.Vcf.rewind(gzip_handle)
+
+    bgzf_handle = Noodlex.Vcf.get_handle(@test_bgzf_vcf_path)
+    assert Noodlex.Vcf.get_all_records(bgzf_handle) == expected
+    assert :ok = Noodlex.Vcf.rewind(bgzf_handle)
+    assert Noodlex.Vcf.get_record(bgzf_handle) == hd(expected)
+  end
+
+  test "extracts the fileformat version and rejects unsupported ones" do
+    assert %Noodlex.Vcf.Header{fileformat: %{major: 4, minor: 3}} =
+             @sites_only_vcf_path |> Noodlex.Vcf.get_handle() |> Noodlex.Vcf.get_header()
+
+    assert %Noodlex.Vcf.Header{fileformat: %{major: 4, minor: 2}} =
+             @v4_2_vcf_path |> Noodlex.Vcf.get_handle() |> Noodlex.Vcf.get_header()
+
+    assert {:error, {:unsupported_file_format, 4, 4}} = Noodlex.Vcf.get_handle(@v4_4_vcf_path)
+  end
+
+  test "lists the INFO and FORMAT keys declared in the header" do
+    handle = Noodlex.Vcf.get_handle(@stats_vcf_path)
+    assert ["NS", "AN", "AC"] = Noodlex.Vcf.info_keys(handle)
+    assert ["GT"] = Noodlex.Vcf.format_keys(handle)
+
+    handle = Noodlex.Vcf.get_handle(@typed_genotypes_vcf_path)
+    assert [] = Noodlex.Vcf.info_keys(handle)
+    assert ["GT", "AD", "DP", "GQ", "GL", "PL"] = Noodlex.Vcf.format_keys(handle)
+  end
+
+  test "reads the same records with a larger read buffer" do
+    expected = @test_vcf_path |> Noodlex.Vcf.get_handle() |> Noodlex.Vcf.get_all_records()
+
+    {time, records} =
+      :timer.tc(fn ->
+        @test_vcf_path
+        |> Noodlex.Vcf.get_handle(buffer_size: 1024 * 1024)
+        |> Noodlex.Vcf.get_all_records()
+      end)
+
+    assert records == expected
+    IO.puts("Time elapsed for read with a 1 MiB buffer: #{div(time, 1000)} ms")
+
+    assert_raise ArgumentError, fn -> Noodlex.Vcf.get_handle(@test_vcf_path, buffer_size: 0) end
+  end
+
+  test "lenient batches keep the good records and report the bad lines" do
+    handle = Noodlex.Vcf.get_handle(@one_bad_record_vcf_path)
+
+    assert {records, [{10, reason}]} = Noodlex.Vcf.get_records_lenient(handle, 100)
+    assert is_binary(reason)
+    assert length(records) == 10
+    assert Enum.map(records, & &1.position) == for(i <- 1..11, i != 6, do: i * 100)
+    assert {[], []} = Noodlex.Vcf.get_records_lenient(handle, 100)
+  end
+
+  test "reports each sample's ploidy from its GT allele count" do
+    handle = Noodlex.Vcf.get_handle(@ploidy_vcf_path)
+
+    assert %{chromosome: "chrY", ploidy: ploidy} = Noodlex.Vcf.get_record(handle)
+    assert ploidy == %{"male" => 1, "female" => nil, "unknown" => nil}
+
+    assert %{chromosome: "chr1", ploidy: ploidy} = Noodlex.Vcf.get_record(handle)
+    assert ploidy == %{"male" => 2, "female" => 2, "unknown" => 2}
+
+    assert %Noodlex.Vcf.Record{ploidy: ploidy} =
+             @sites_only_vcf_path |> Noodlex.Vcf.get_handle() |> Noodlex.Vcf.get_record()
+
+    assert ploidy == %{}
+  end
+
+  test "normalizes differently ordered but equal records to the same struct" do
+    a = @normalize_a_vcf_path |> Noodlex.Vcf.get_handle() |> Noodlex.Vcf.get_record()
+    b = @normalize_b_vcf_path |> Noodlex.Vcf.get_handle() |> Noodlex.Vcf.get_record()
+    refute a == b
+
+    normalized = Noodlex.Vcf.normalize_record(a)
+    assert Noodlex.Vcf.normalize_record(b) == normalized
+    assert Noodlex.Vcf.normalize_record(normalized) == normalized
+
+    assert %Noodlex.Vcf.Record{
+             reference_bases: "A",
+             alternate_bases: ["C", "G"],
+             format: ["GT", "AD", "PL"],
+             reserved_info: %{allele_count: [1, 2]}
+           } = normalized
+
+    assert %{"AD" => [5, 3, 2], "GT" => %{alleles: [0, 1]}} = normalized.genotypes["sample1"]
+    assert %{"PL" => [1, 2, 3, 4, 5, 6], "GT" => %{alleles: [1, 2]}} =
+             normalized.genotypes["sample2"]
+  end
+
+  @tag :tmp_dir
+  test "refuses to index a file format version get_handle rejects", %{tmp_dir: tmp_dir} do
+    path = Path.join(tmp_dir, "v4_4.vcf.gz")
+    File.write!(path, @v4_4_vcf_path |> File.read!() |> bgzip())
+
+    assert {:error, {:unsupported_file_format, 4, 4}} = Noodlex.Vcf.get_handle(path)
+    assert {:error, {:unsupported_file_format, 4, 4}} = Noodlex.Vcf.index(path)
+    refute File.exists?(path <> ".tbi")
+  end
+
+  @tag :tmp_dir
+  test "indexes a bgzipped file and queries it by region", %{tmp_dir: tmp_dir} do
+    path = Path.join(tmp_dir, "test.vcf.gz")
+    File.cp!(@test_bgzf_vcf_path, path)
+
+    assert 2588 = Noodlex.Vcf.index(path)
+    assert File.exists?(path <> ".tbi")
+
+    expected =
+      @test_vcf_path
+      |> Noodlex.Vcf.get_handle()
+      |> Noodlex.Vcf.get_all_records()
+      |> Enum.filter(&(&1.chromosome == "chr2" and &1.position <= 50_000_000))
+      |> Enum.map(&%{&1 | line: 0})
+
+    handle = Noodlex.Vcf.get_handle(path)
+    assert Noodlex.Vcf.query(handle, "chr2:1-50000000") == expected
+    assert [] = Noodlex.Vcf.query(handle, "chrUn:1-100")
+    assert %Noodlex.Vcf.Record{chromosome: "chr1"} = Noodlex.Vcf.get_record(handle)
+
+    assert {:error, :not_bgzf} = Noodlex.Vcf.index(@test_gzip_vcf_path)
+
+    assert {:error, :not_bgzf} =
+             @test_vcf_path |> Noodlex.Vcf.get_handle() |> Noodlex.Vcf.query("chr1:1-100")
+  end
+
+  @tag :tmp_dir
+  test "summarizes a region's records natively", %{tmp_dir: tmp_dir} do
+    path = Path.join(tmp_dir, "test.vcf.gz")
+    File.cp!(@test_bgzf_vcf_path, path)
+    Noodlex.Vcf.index(path)
+    handle = Noodlex.Vcf.get_handle(path)
+
+    records = Noodlex.Vcf.query(handle, "chr2:1-50000000")
+    qualities = records |> Enum.map(& &1.quality_score) |> Enum.reject(&is_nil/1)
+
+    variant_types = records |> Enum.flat_map(& &1.variant_type) |> Enum.frequencies()
+
+    summary = Noodlex.Vcf.region_summary(handle, "chr2:1-50000000")
+    assert summary.record_count == length(records)
+    assert summary.variant_types == variant_types
+    assert_in_delta summary.mean_quality_score, Enum.sum(qualities) / length(qualities), 1.0e-9
+
+    assert %{record_count: 0, variant_types: %{}, mean_quality_score: nil} =
+             Noodlex.Vcf.region_summary(handle, "chrUn:1-100")
+
+    assert {:error, {:invalid_region, _}} = Noodlex.Vcf.region_summary(handle, "chr1:abc-200")
+  end
+
+  test "classifies each alternate allele's variant type by length" do
+    records = @variant_types_vcf_path |> Noodlex.Vcf.get_handle() |> Noodlex.Vcf.get_all_records()
+
+    assert Enum.map(records, & &1.variant_type) == [
+             [:snv],
+             [:insertion],
+             [:deletion],
+             [:mnv],
+             [:deletion, :insertion, :mnv, :other]
+           ]
+  end
+
+  test "gives each record a position struct spanning its reference bases or END" do
+    [snv, _insertion, deletion | _rest] =
+      @variant_types_vcf_path |> Noodlex.Vcf.get_handle() |> Noodlex.Vcf.get_all_records()
+
+    assert snv.interval == %Noodlex.Vcf.Position{chrom: "chr1", start: 100, end: 100}
+    assert deletion.interval == %Noodlex.Vcf.Position{chrom: "chr1", start: 300, end: 302}
+    assert deletion.position == deletion.interval.start
+
+    [segment | _rest] =
+      @copy_number_vcf_path |> Noodlex.Vcf.get_handle() |> Noodlex.Vcf.get_all_records()
+
+    assert %Noodlex.Vcf.Position{start: 1000, end: 5000} = segment.interval
+  end
+
+  @tag :tmp_dir
+  test "region queries run alongside another read on the handle", %{tmp_dir: tmp_dir} do
+    plain_path = Path.join(tmp_dir, "large.vcf")
+    path = Path.join(tmp_dir, "large.vcf.gz")
+
+    File.write!(plain_path, [
+      "##fileformat=VCFv4.3\n",
+      "##contig=<ID=chr1,length=248956422>\n",
+      "##INFO=<ID=DP,Number=1,Type=Integer,Description=\"Depth\">\n",
+      "#CHROM\tPOS\tID\tREF\tALT\tQUAL\tFILTER\tINFO\n",
+      for(position <- 1..200_000, do: "chr1\t#{position}\t.\tA\tC\t50\tPASS\tDP=10\n")
+    ])
+
+    Noodlex.Vcf.convert(plain_path, path)
+    Noodlex.Vcf.index(path)
+    handle = Noodlex.Vcf.get_handle(path)
+    reader = Task.async(fn -> handle |> Noodlex.Vcf.get_all_records() |> length() end)
+    await_nif_call(reader.pid, :get_all_records)
+
+    # That queries never wait on the stream is checked directly by the Rust unit test
+    # region_reads_never_take_the_stream_lock; here they only have to agree with the read.
+    results =
+      ["chr1:1000-1999", "chr1:150000-150999"]
+      |> Enum.map(fn region -> Task.async(fn -> Noodlex.Vcf.query(handle, region) end) end)
+      |> Task.await_many(60_000)
+
+    assert Enum.map(results, &length/1) == [1_000, 1_000]
+    assert Task.await(reader, 60_000) == 200_000
+  end
+
+  test "returns IDs as a list, empty for a missing ID" do
+    records = @ids_vcf_path |> Noodlex.Vcf.get_handle() |> Noodlex.Vcf.get_all_records()
+
+    assert Enum.map(records, & &1.ids) == [[], ["rs123"], ["rs123", "rs456"]]
+  end
+
+  @tag :tmp_dir
+  test "copies PASS records natively from a reader to a writer", %{tmp_dir: tmp_dir} do
+    path = Path.join(tmp_dir, "pass_only.vcf")
+    expected = @test_vcf_path |> Noodlex.Vcf.get_handle() |> Noodlex.Vcf.get_all_records()
+    expected_pass = Enum.filter(expected, &(&1.filters == :pass))
+
+    handle = Noodlex.Vcf.get_handle(@test_vcf_path)
+    writer = Noodlex.Vcf.get_writer(path, handle)
+    assert Noodlex.Vcf.copy_records(handle, writer, pass_only: true) == length(expected_pass)
+
+    copied = path |> Noodlex.Vcf.get_handle() |> Noodlex.Vcf.get_all_records()
+    assert Enum.map(copied, &{&1.chromosome, &1.position}) ==
+             Enum.map(expected_pass, &{&1.chromosome, &1.position})
+
+    handle = Noodlex.Vcf.get_handle(@test_vcf_path)
+    writer = Noodlex.Vcf.get_writer(path, handle)
+    assert 2588 = Noodlex.Vcf.copy_records(handle, writer, [])
+  end
+
+  test "surfaces the structural-variant INFO definitions of an SV caller header" do
+    handle = Noodlex.Vcf.get_handle(@manta_sv_vcf_path)
+    header = Noodlex.Vcf.get_header(handle)
+    sv_infos = Noodlex.Vcf.sv_info_definitions(handle)
+
+    sv_keys = ~w(IMPRECISE SVTYPE SVLEN END CIPOS CIEND MATEID EVENT HOMLEN HOMSEQ)a
+    assert sv_infos |> Map.keys() |> Enum.sort() == Enum.sort(sv_keys)
+
+    assert sv_infos == Map.take(header.infos, Map.keys(sv_infos))
+    assert %{number: :unknown, type_: :integer} = sv_infos[:SVLEN]
+    assert %{number: 2, type_: :integer} = sv_infos[:CIPOS]
+    assert %{number: 0, type_: :flag} = sv_infos[:IMPRECISE]
+    assert %{number: 1, type_: :integer} = header.infos[:BND_DEPTH]
+    refute Map.has_key?(sv_infos, :CIGAR)
+  end
+
+  test "`next_record` keeps end of file out of the error channel" do
+    handle = Noodlex.Vcf.get_handle(@malformed_vcf_path)
+
+    assert {:ok, %Noodlex.Vcf.Record{position: 100}} = Noodlex.Vcf.next_record(handle)
+    assert {:error, {:parse, 6, _reason}} = Noodlex.Vcf.next_record(handle)
+    assert {:ok, %Noodlex.Vcf.Record{position: 300}} = Noodlex.Vcf.next_record(handle)
+    assert :end_of_file = Noodlex.Vcf.next_record(handle)
+    assert {:error, :end_of_file} = Noodlex.Vcf.get_record(handle)
+  end
+
+  test "extracts only the allowlisted INFO and FORMAT keys" do
+    opts = [info: ["AF", "DP"], format: ["GT"]]
+
+    [record | _rest] =
+      @test_vcf_path |> Noodlex.Vcf.get_handle() |> Noodlex.Vcf.get_records(10, opts)
+
+    assert record.info == %{"DP" => "DP=1"}
+    assert record.reserved_info == %{total_depth: 1}
+    assert record.format == ["GT"]
+    assert Enum.all?(Map.values(record.genotypes), &(Map.keys(&1) == ["GT"]))
+
+    {full_time, full} =
+      :timer.tc(fn ->
+        @test_vcf_path |> Noodlex.Vcf.get_handle() |> Noodlex.Vcf.get_records(2588)
+      end)
+
+    {allowlist_time, allowlisted} =
+      :timer.tc(fn ->
+        @test_vcf_path |> Noodlex.Vcf.get_handle() |> Noodlex.Vcf.get_records(2588, opts)
+      end)
+
+    assert length(allowlisted) == length(full)
+    IO.puts("Time elapsed for batched read of every field: #{div(full_time, 1000)} ms")
+    IO.puts("Time elapsed for allowlisted batched read: #{div(allowlist_time, 1000)} ms")
+  end
+
+  test "reads one contig at a time and leaves the next contig unread" do
+    handle = Noodlex.Vcf.get_handle(@test_vcf_path)
+
+    chr1 = Noodlex.Vcf.get_records_for_contig(handle, "chr1", 1_000)
+    assert length(chr1) == 135
+    assert Enum.all?(chr1, &(&1.chromosome == "chr1"))
+
+    assert [%Noodlex.Vcf.Record{chromosome: "chr2"}] =
+             Noodlex.Vcf.get_records_for_contig(handle, "chr2", 1)
+
+    assert %Noodlex.Vcf.Record{chromosome: "chr2"} = Noodlex.Vcf.get_record(handle)
+    assert {:error, :not_found} = Noodlex.Vcf.get_records_for_contig(handle, "chrUn", 1)
+
+    # Without an index a contig behind the stream is found by rewinding once the end is reached.
+    assert ^chr1 = Noodlex.Vcf.get_records_for_contig(handle, "chr1", 1_000)
+    assert %Noodlex.Vcf.Record{chromosome: "chr2"} = Noodlex.Vcf.get_record(handle)
+
+    assert {:error, :not_seekable} =
+             @test_gzip_vcf_path
+             |> Noodlex.Vcf.get_handle()
+             |> Noodlex.Vcf.get_records_for_contig("chr1", 1)
+  end
+
+  @tag :tmp_dir
+  test "jumps to a contig through the tabix index", %{tmp_dir: tmp_dir} do
+    path = Path.join(tmp_dir, "test.vcf.gz")
+    File.cp!(@test_bgzf_vcf_path, path)
+    Noodlex.Vcf.index(path)
+
+    all_records =
+      @test_vcf_path
+      |> Noodlex.Vcf.get_handle()
+      |> Noodlex.Vcf.get_all_records()
+      |> Enum.map(&%{&1 | line: 0})
+
+    expected = Enum.filter(all_records, &(&1.chromosome == "chr3"))
+    handle = Noodlex.Vcf.get_handle(path)
+    assert Noodlex.Vcf.get_records_for_contig(handle, "chr3", 1_000) == expected
+    assert %Noodlex.Vcf.Record{chromosome: "chr4", line: 0} = Noodlex.Vcf.get_record(handle)
+
+    # A contig behind the stream is jumped back to just like one ahead of it.
+    behind = Enum.filter(all_records, &(&1.chromosome == "chr2"))
+    assert Noodlex.Vcf.get_records_for_contig(handle, "chr2", 1_000) == behind
+    assert %Noodlex.Vcf.Record{chromosome: "chr3", line: 0} = Noodlex.Vcf.get_record(handle)
+
+    :ok = Noodlex.Vcf.rewind(handle)
+    assert %Noodlex.Vcf.Record{chromosome: "chr1", line: line} = Noodlex.Vcf.get_record(handle)
+    assert line > 0
+  end
+
+  test "computes AF from genotypes when INFO lacks it" do
+    expected = @af_vcf_path |> Noodlex.Vcf.get_handle() |> Noodlex.Vcf.get_records(2)
+
+    computed =
+      @af_stripped_vcf_path
+      |> Noodlex.Vcf.get_handle()
+      |> Noodlex.Vcf.get_records(3, compute_allele_frequencies: true)
+
+    assert [[0.5], [0.25, 0.25], nil] == Enum.map(computed, & &1.reserved_info.allele_frequencies)
+
+    Enum.zip(expected, computed)
+    |> Enum.each(fn {expected, computed} ->
+      assert computed.reserved_info.allele_frequencies ==
+               expected.reserved_info.allele_frequencies
+    end)
+  end
+
+  test "returns record lines verbatim" do
+    [first, second | _rest] =
+      @test_vcf_path
+      |> File.stream!()
+      |> Stream.reject(&String.starts_with?(&1, "#"))
+      |> Enum.map(&String.trim_trailing(&1, "\n"))
+
+    handle = Noodlex.Vcf.get_handle(@test_vcf_path)
+    assert Noodlex.Vcf.get_raw_record(handle) == first
+    assert [_chrom, _pos, _id, _ref, _alt, _qual, _filter, _info, _format, _sample] =
+             String.split(first, "\t")
+
+    assert Noodlex.Vcf.get_raw_record(handle) == second
+    assert %Noodlex.Vcf.Record{} = Noodlex.Vcf.get_record(handle)
+
+    handle = Noodlex.Vcf.get_handle(@no_records_vcf_path)
+    assert :end_of_file = Noodlex.Vcf.get_raw_record(handle)
+  end
+
+  test "reports the header line that failed to parse" do
+    assert {:error, {:header, 4, reason}} = Noodlex.Vcf.get_handle(@bad_header_vcf_path)
+    assert reason =~ "invalid INFO"
+  end
+
+  @tag :tmp_dir
+  test "appends records to an existing file without a second header", %{tmp_dir: tmp_dir} do
+    path = Path.join(tmp_dir, "appended.vcf")
+    File.cp!(@stats_vcf_path, path)
+    records = path |> Noodlex.Vcf.get_handle() |> Noodlex.Vcf.get_records(2)
+
+    writer = Noodlex.Vcf.open_writer_append(path)
+    assert :ok = Noodlex.Vcf.write_records(writer, records)
+
+    original_header = @stats_vcf_path |> Noodlex.Vcf.get_handle() |> Noodlex.Vcf.get_header()
+    handle = Noodlex.Vcf.get_handle(path)
+    assert Noodlex.Vcf.get_header(handle) == original_header
+    assert length(Noodlex.Vcf.get_all_records(handle)) == 5
+
+    assert {:error, :compressed} = Noodlex.Vcf.open_writer_append(@test_bgzf_vcf_path)
+    assert {:error, :compressed} = Noodlex.Vcf.open_writer_append(@test_gzip_vcf_path)
+  end
+
+  test "parses the mate of each breakend orientation" do
+    alias Noodlex.Vcf.Record.Breakend
+
+    records = @breakends_vcf_path |> Noodlex.Vcf.get_handle() |> Noodlex.Vcf.get_all_records()
+
+    assert Enum.map(records, & &1.breakends) == [
+             [%Breakend{mate_chrom: "17", mate_pos: 198_982, bracket: "]", join: :after}],
+             [%Breakend{mate_chrom: "13", mate_pos: 123_456, bracket: "]", join: :before}],
+             [%Breakend{mate_chrom: "2", mate_pos: 321_682, bracket: "[", join: :after}],
+             [%Breakend{mate_chrom: "17", mate_pos: 198_983, bracket: "[", join: :before}],
+             [nil, nil]
+           ]
+
+    assert %{mate_breakend_ids: ["bnd_Y"]} = hd(records).reserved_info
+  end
+
+  test "scans only the records inside a coordinate window" do
+    handle = Noodlex.Vcf.get_handle(@test_vcf_path)
+
+    records = Noodlex.Vcf.get_records_in_range(handle, "chr1", 10_400_000, 16_000_000, 1_000)
+    assert length(records) == 29
+
+    assert Enum.all?(records, fn record ->
+             record.chromosome == "chr1" and record.position in 10_400_000..16_000_000
+           end)
+
+    # The scan stops at the first record past the window instead of reading to the end.
+    assert %Noodlex.Vcf.Record{chromosome: "chr1", position: 17_345_073} =
+             Noodlex.Vcf.get_record(handle)
+
+    assert {:error, :not_found} = Noodlex.Vcf.get_records_in_range(handle, "chrUn", 1, 100, 1)
+  end
+
+  test "keeps an entry for a declared sample whose column is missing" do
+    record = @missing_samples_vcf_path |> Noodlex.Vcf.get_handle() |> Noodlex.Vcf.get_record()
+
+    assert %{
+             "sample1" => %{"GT" => %{alleles: [0, 1]}, "DP" => 10},
+             "sample2" => %{"GT" => %{alleles: [1, 1]}, "DP" => 12},
+             "sample3" => nil
+           } = record.genotypes
+
+    assert map_size(record.genotypes) == 3
+    assert record.ploidy == %{"sample1" => 2, "sample2" => 2, "sample3" => nil}
+  end
+
+  test "reads a header without opening a handle" do
+    for path <- [@test_vcf_path, @test_bgzf_vcf_path, @trio_vcf_path] do
+      assert Noodlex.Vcf.read_header_only(path) ==
+               path |> Noodlex.Vcf.get_handle() |> Noodlex.Vcf.get_header()
+    end
+
+    assert {:error, {:unsupported_file_format, 4, 4}} =
+             Noodlex.Vcf.read_header_only(@v4_4_vcf_path)
+
+    assert {:error, {:header, 4, _reason}} = Noodlex.Vcf.read_header_only(@bad_header_vcf_path)
+  end
+
+  test "peeks at end of file without consuming a record" do
+    for path <- [@test_vcf_path, @test_bgzf_vcf_path] do
+      handle = Noodlex.Vcf.get_handle(path)
+      refute Noodlex.Vcf.at_eof?(handle)
+
+      Noodlex.Vcf.get_records(handle, 1_000)
+      refute Noodlex.Vcf.at_eof?(handle)
+      refute Noodlex.Vcf.at_eof?(handle)
+
+      assert length(Noodlex.Vcf.get_all_records(handle)) == 1_588
+      assert Noodlex.Vcf.at_eof?(handle)
+      assert {:error, :end_of_file} = Noodlex.Vcf.get_record(handle)
+    end
+
+    assert @no_records_vcf_path |> Noodlex.Vcf.get_handle() |> Noodlex.Vcf.at_eof?()
+  end
+
+  test "represents missing values with the sentinel chosen on the handle" do
+    for missing <- [nil, :missing, ""] do
+      record =
+        @all_missing_vcf_path
+        |> Noodlex.Vcf.get_handle(missing: missing)
+        |> Noodlex.Vcf.get_record()
+
+      assert record.quality_score == missing
+      assert record.reserved_info.total_depth == missing
+      assert record.reserved_info.allele_frequencies == [missing, 0.5]
+      assert %{"DP" => ^missing, "AD" => [^missing, 3, ^missing]} = record.genotypes["sample1"]
+    end
+  end
+
+  test "overrides the handle's missing-value sentinel per call" do
+    handle = Noodlex.Vcf.get_handle(@all_missing_vcf_path, missing: :missing)
+
+    assert [%{quality_score: "", reserved_info: %{total_depth: ""}}] =
+             Noodlex.Vcf.get_records(handle, 1, missing: "")
+
+    Noodlex.Vcf.rewind(handle)
+    assert %{quality_score: :missing} = Noodlex.Vcf.get_record(handle)
+
+    assert_raise ArgumentError, fn ->
+      Noodlex.Vcf.get_handle(@all_missing_vcf_path, missing: "NA")
+    end
+  end
+
+  @tag :tmp_dir
+  test "counts records from the index the same as by scanning", %{tmp_dir: tmp_dir} do
+    path = Path.join(tmp_dir, "test.vcf.gz")
+    File.cp!(@test_bgzf_vcf_path, path)
+
+    scanned = path |> Noodlex.Vcf.get_handle() |> Noodlex.Vcf.count_records()
+    assert scanned == 2588
+
+    Noodlex.Vcf.index(path)
+    handle = Noodlex.Vcf.get_handle(path)
+    assert Noodlex.Vcf.count_records(handle) == scanned
+    assert %Noodlex.Vcf.Record{chromosome: "chr1"} = Noodlex.Vcf.get_record(handle)
+
+    assert @test_vcf_path |> Noodlex.Vcf.get_handle() |> Noodlex.Vcf.count_records() == scanned
+  end
+
+  test "flags whether a record passed its filters" do
+    records = @filters_vcf_path |> Noodlex.Vcf.get_handle() |> Noodlex.Vcf.get_all_records()
+
+    assert Enum.map(records, &{&1.filters, &1.passed}) == [
+             {:pass, true},
+             {{:fail, ["q10", "s50"]}, false},
+             {:none, nil}
+           ]
+  end
+
+  test "returns the last records of a file without moving the handle" do
+    handle = Noodlex.Vcf.get_handle(@variant_types_vcf_path)
+    all_records = Noodlex.Vcf.get_all_records(Noodlex.Vcf.get_handle(@variant_types_vcf_path))
+
+    assert Noodlex.Vcf.tail(handle, 3) == Enum.take(all_records, -3)
+    assert Noodlex.Vcf.tail(handle, 10) == all_records
+    assert Noodlex.Vcf.tail(handle, 0) == []
+    assert Noodlex.Vcf.get_record(handle) == hd(all_records)
+  end
+
+  @tag :tmp_dir
+  test "starts a tail near the end through the tabix index", %{tmp_dir: tmp_dir} do
+    path = Path.join(tmp_dir, "test.vcf.gz")
+    File.cp!(@test_bgzf_vcf_path, path)
+    Noodlex.Vcf.index(path)
+
+    expected =
+      @test_vcf_path
+      |> Noodlex.Vcf.get_handle()
+      |> Noodlex.Vcf.get_all_records()
+      |> Enum.take(-3)
+      |> Enum.map(&%{&1 | line: 0})
+
+    assert path |> Noodlex.Vcf.get_handle() |> Noodlex.Vcf.tail(3) == expected
+  end
+
+  test "parses the CIGAR INFO field into operations per alternate allele" do
+    records = @cigar_vcf_path |> Noodlex.Vcf.get_handle() |> Noodlex.Vcf.get_all_records()
+
+    assert Enum.map(records, & &1.reserved_info.cigar) == [
+             [[{"M", 1}, {"I", 1}, {"M", 2}]],
+             [[{"M", 1}, {"D", 1}], [{"M", 2}, {"I", 1}]],
+             ["1Q"]
+           ]
+  end
+
+  test "fails on an INFO ID too long for an atom and keeps the handle usable" do
+    handle = Noodlex.Vcf.get_handle(@long_info_key_vcf_path)
+    assert {:error, {:info_id_too_long, id}} = Noodlex.Vcf.get_header(handle)
+    assert String.length(id) > 255
+    assert {:error, {:info_id_too_long, ^id}} = Noodlex.Vcf.describe_info_key(handle, id)
+
+    assert %Noodlex.Vcf.Record{position: 100} = Noodlex.Vcf.get_record(handle)
+    assert :ok = Noodlex.Vcf.rewind(handle)
+    assert Noodlex.Vcf.count_records(handle) == 1
+  end
+
+  test "checks records are sorted by header contig order and position" do
+    assert :ok = @fullgenome_vcf_path |> Noodlex.Vcf.get_handle() |> Noodlex.Vcf.check_sorted()
+
+    handle = Noodlex.Vcf.get_handle(@unsorted_vcf_path)
+    assert {:unsorted, 6} = Noodlex.Vcf.check_sorted(handle)
+    assert %Noodlex.Vcf.Record{position: 100} = Noodlex.Vcf.get_record(handle)
+
+    # The header declares chr10 right after chr1, but its records follow chr9's.
+    assert {:unsorted, 1320} =
+             @test_vcf_path |> Noodlex.Vcf.get_handle() |> Noodlex.Vcf.check_sorted()
+  end
+
+  test "merges single-sample headers into one multi-sample header" do
+    handles = Enum.map([@merge_a_vcf_path, @merge_b_vcf_path], &Noodlex.Vcf.get_handle/1)
+    merged = Noodlex.Vcf.merge_headers(handles)
+
+    assert merged.sample_names == ["sample_a", "sample_b"]
+    assert Enum.sort(Map.keys(merged.infos)) == [:AF, :DP]
+    assert %{number: 1, type_: :integer} = merged.infos[:DP]
+    assert Enum.sort(Map.keys(merged.formats)) == ["DP", "GT"]
+    assert %Noodlex.Vcf.Header.Format{number: 1, type_: :integer} = merged.formats["DP"]
+    assert merged.contigs == %{"chr1" => 248_956_422, "chr2" => 242_193_529}
+
+    conflicting = Noodlex.Vcf.get_handle(@merge_conflict_vcf_path)
+
+    assert {:error, {:conflicting_definition, :info, "DP"}} =
+             Noodlex.Vcf.merge_headers([hd(handles), conflicting])
+
+    assert {:error, {:duplicate_sample, "sample_a"}} =
+             Noodlex.Vcf.merge_headers([hd(handles), hd(handles)])
+  end
+
+  test "keys Number=A and Number=R INFO values by allele" do
+    [triallelic, short_af] =
+      @allele_info_vcf_path |> Noodlex.Vcf.get_handle() |> Noodlex.Vcf.get_all_records()
+
+    assert triallelic.allele_info == %{
+             "AF" => %{"C" => 0.25, "G" => 0.5},
+             "AD" => %{"A" => 10, "C" => 5, "G" => nil}
+           }
+
+    # One AF value for two alternate alleles cannot be assigned to either.
+    assert short_af.allele_info == %{}
+  end
+
+  @tag :tmp_dir
+  test "estimates the records remaining on a uniform file", %{tmp_dir: tmp_dir} do
+    path = Path.join(tmp_dir, "uniform.vcf")
+
+    records =
+      for position <- 1_000_001..1_002_000 do
+        "chr1\t#{position}\t.\tA\tC\t50\tPASS\tDP=10\n"
+      end
+
+    File.write!(path, [
+      "##fileformat=VCFv4.3\n##contig=<ID=chr1,length=248956422>\n",
+      "##INFO=<ID=DP,Number=1,Type=Integer,Description=\"Depth\">\n",
+      "#CHROM\tPOS\tID\tREF\tALT\tQUAL\tFILTER\tINFO\n"
+      | records
+    ])
+
+    handle = Noodlex.Vcf.get_handle(path)
+    assert Noodlex.Vcf.estimate_remaining(handle) == nil
+
+    Noodlex.Vcf.get_records(handle, 500)
+    assert_in_delta Noodlex.Vcf.estimate_remaining(handle), 1_500, 15
+
+    Noodlex.Vcf.get_all_records(handle)
+    assert Noodlex.Vcf.estimate_remaining(handle) == 0
+
+    assert {:error, :not_seekable} =
+             @test_gzip_vcf_path |> Noodlex.Vcf.get_handle() |> Noodlex.Vcf.estimate_remaining()
+  end
+
+  @tag :tmp_dir
+  test "writes bgzipped output for a .gz path", %{tmp_dir: tmp_dir} do
+    path = Path.join(tmp_dir, "written.vcf.gz")
+    handle = Noodlex.Vcf.get_handle(@test_vcf_path)
+    records = Noodlex.Vcf.get_records(handle, 100)
+
+    writer = Noodlex.Vcf.get_writer(path, handle)
+    assert :ok = Noodlex.Vcf.write_records(writer, records)
+    assert :ok = Noodlex.Vcf.close_writer(writer)
+    assert :ok = Noodlex.Vcf.close_writer(writer)
+    assert {:error, :closed} = Noodlex.Vcf.write_record(writer, hd(records))
+
+    written = path |> Noodlex.Vcf.get_handle() |> Noodlex.Vcf.get_all_records()
+    assert Enum.map(written, &Map.delete(&1, :line)) == Enum.map(records, &Map.delete(&1, :line))
+
+    assert 100 = Noodlex.Vcf.index(path)
+  end
+
+  @tag :tmp_dir
+  test "returns INFO in column order with ordered_info: true", %{tmp_dir: tmp_dir} do
+    info_keys = fn raw_record ->
+      raw_record
+      |> String.split("\t")
+      |> Enum.at(7)
+      |> String.split(";")
+      |> Enum.map(&(&1 |> String.split("=") |> hd()))
+    end
+
+    handle = Noodlex.Vcf.get_handle(@test_vcf_path)
+    source_keys = handle |> Noodlex.Vcf.get_raw_record() |> info_keys.()
+    Noodlex.Vcf.rewind(handle)
+
+    [record] = Noodlex.Vcf.get_records(handle, 1, ordered_info: true)
+    assert Enum.map(record.info, &elem(&1, 0)) == source_keys
+
+    path = Path.join(tmp_dir, "ordered.vcf")
+    writer = Noodlex.Vcf.get_writer(path, handle)
+    assert :ok = Noodlex.Vcf.write_record(writer, record)
+
+    written = path |> Noodlex.Vcf.get_handle() |> Noodlex.Vcf.get_raw_record()
+    assert info_keys.(written) == source_keys
+  end
+
+  @tag :tmp_dir
+  test "reports how a handle was opened", %{tmp_dir: tmp_dir} do
+    assert %{source: @test_vcf_path, compression: :none, format: :vcf, index_loaded: false} =
+             @test_vcf_path |> Noodlex.Vcf.get_handle_mmap() |> Noodlex.Vcf.handle_info()
+
+    assert %{source: @test_gzip_vcf_path, compression: :gzip, index_loaded: false} =
+             @test_gzip_vcf_path |> Noodlex.Vcf.get_handle() |> Noodlex.Vcf.handle_info()
+
+    path = Path.join(tmp_dir, "test.vcf.gz")
+    File.cp!(@test_bgzf_vcf_path, path)
+    Noodlex.Vcf.index(path)
+    handle = Noodlex.Vcf.get_handle(path)
+    assert %{source: ^path, compression: :bgzf, index_loaded: false} =
+             Noodlex.Vcf.handle_info(handle)
+
+    Noodlex.Vcf.query(handle, "chr1:1-100")
+    assert %{index_loaded: true} = Noodlex.Vcf.handle_info(handle)
+  end
+
+  @tag :tmp_dir
+  test "writes back a record with an added INFO field", %{tmp_dir: tmp_dir} do
+    path = Path.join(tmp_dir, "annotated.vcf")
+    handle = Noodlex.Vcf.get_handle(@annotate_vcf_path)
+    record = Noodlex.Vcf.get_record(handle)
+
+    annotated = %{record | info: Map.put(record.info, "FOO", "bar")}
+    writer = Noodlex.Vcf.get_writer(path, handle)
+    assert :ok = Noodlex.Vcf.write_record(writer, annotated)
+
+    assert %{info: %{"DP" => "DP=10", "FOO" => "FOO=bar"}} =
+             path |> Noodlex.Vcf.get_handle() |> Noodlex.Vcf.get_record()
+
+    assert {:error, {:invalid_record, "INFO key BAZ is not declared in the header"}} =
+             Noodlex.Vcf.write_record(writer, %{record | info: Map.put(record.info, "BAZ", "1")})
+  end
+
+  test "lists the contigs records use in first-seen order" do
+    handle = Noodlex.Vcf.get_handle(@observed_contigs_vcf_path)
+
+    assert Noodlex.Vcf.observed_contigs(handle) == ["chr3", "chrUn", "chr1"]
+    assert %Noodlex.Vcf.Record{chromosome: "chr3", position: 100} = Noodlex.Vcf.get_record(handle)
+  end
+
+  test "counts records per contig in first-seen order in one scan" do
+    unsorted = Noodlex.Vcf.get_handle(@observed_contigs_vcf_path)
+    assert Noodlex.Vcf.records_per_contig(unsorted) == [{"chr3", 3}, {"chrUn", 1}, {"chr1", 1}]
+
+    handle = Noodlex.Vcf.get_handle(@test_vcf_path)
+    counts = Noodlex.Vcf.records_per_contig(handle)
+    records = Noodlex.Vcf.get_all_records(handle)
+
+    assert Enum.map(counts, &elem(&1, 0)) == records |> Enum.map(& &1.chromosome) |> Enum.dedup()
+    assert counts |> Enum.map(&elem(&1, 1)) |> Enum.sum() == Noodlex.Vcf.count_records(handle)
+    assert Map.new(counts) == Enum.frequencies_by(records, & &1.chromosome)
+  end
+
+  test "lenient batches fill up to count with good records past bad lines" do
+    handle = Noodlex.Vcf.get_handle(@interleaved_bad_vcf_path)
+
+    assert {records, errors} = Noodlex.Vcf.get_records_lenient(handle, 4)
+    assert Enum.map(records, & &1.position) == [100, 300, 500, 700]
+    assert Enum.map(errors, &elem(&1, 0)) == [5, 7, 9]
+
+    assert {[%{position: 800}], []} = Noodlex.Vcf.get_records_lenient(handle, 4)
+  end
+
+  @tag :tmp_dir
+  test "format follows each record's FORMAT column, not the header", %{tmp_dir: tmp_dir} do
+    handle = Noodlex.Vcf.get_handle(@format_order_vcf_path)
+    assert Noodlex.Vcf.format_keys(handle) == ["DP", "AD", "GT"]
+
+    [first, second] = records = Noodlex.Vcf.get_all_records(handle)
+    assert first.format == ["GT", "AD", "DP"]
+    assert %{"GT" => %{alleles: [0, 1]}, "AD" => [7, 3], "DP" => 10} = first.genotypes["S1"]
+    assert %{"GT" => %{alleles: [1, 1]}, "AD" => [0, 12], "DP" => 12} = first.genotypes["S2"]
+    assert second.format == ["AD", "DP"]
+    assert second.genotypes["S1"] == %{"AD" => [8, 0], "DP" => 8}
+    assert second.genotypes["S2"] == %{"AD" => [9, 6], "DP" => 15}
+
+    path = Path.join(tmp_dir, "format_order.vcf")
+    writer = Noodlex.Vcf.get_writer(path, handle)
+    assert :ok = Noodlex.Vcf.write_records(writer, records)
+
+    written = path |> Noodlex.Vcf.get_handle() |> Noodlex.Vcf.get_all_records()
+    assert Enum.map(written, & &1.format) == [["GT", "AD", "DP"], ["AD", "DP"]]
+    assert Enum.map(written, & &1.genotypes) == Enum.map(records, & &1.genotypes)
+  end
+
+  @tag :tmp_dir
+  test "converts VCF to BCF and back without losing anything", %{tmp_dir: tmp_dir} do
+    bcf_path = Path.join(tmp_dir, "test.bcf")
+    vcf_path = Path.join(tmp_dir, "test.vcf")
+
+    assert Noodlex.Vcf.convert(@test_vcf_path, bcf_path) == 2588
+    assert <<0x1F, 0x8B, _rest::binary>> = File.read!(bcf_path)
+    assert Noodlex.Vcf.convert(bcf_path, vcf_path) == 2588
+
+    original = Noodlex.Vcf.get_handle(@test_vcf_path)
+    converted = Noodlex.Vcf.get_handle(vcf_path)
+    assert Noodlex.Vcf.get_header(converted) == Noodlex.Vcf.get_header(original)
+    assert Noodlex.Vcf.get_all_records(converted) == Noodlex.Vcf.get_all_records(original)
+  end
+
+  @tag :tmp_dir
+  test "region strings split on the last colon", %{tmp_dir: tmp_dir} do
+    path = Path.join(tmp_dir, "colon_contig.vcf.gz")
+    assert 5 = Noodlex.Vcf.convert(@colon_contig_vcf_path, path)
+    assert 5 = Noodlex.Vcf.index(path)
+    handle = Noodlex.Vcf.get_handle(path)
+    positions = &Enum.map(Noodlex.Vcf.query(handle, &1), fn record -> record.position end)
+
+    assert positions.("chr1:150-250") == [200]
+    assert positions.("HLA-A*01:01:100-200") == [150]
+    assert positions.("HLA-A*01:01") == [50, 150, 300]
+
+    assert {:error, {:invalid_region, _}} = Noodlex.Vcf.query(handle, "chr1:abc-200")
+    assert {:error, {:invalid_region, _}} = Noodlex.Vcf.query(handle, "chr1:300-100")
+  end
+
+  test "filter_histogram counts each FILTER status without moving the handle" do
+    handle = Noodlex.Vcf.get_handle(@filter_mix_vcf_path)
+
+    assert Noodlex.Vcf.filter_histogram(handle) == %{
+             :pass => 4,
+             :none => 1,
+             "LowQual" => 3,
+             "q10" => 1
+           }
+
+    assert %Noodlex.Vcf.Record{position: 100} = Noodlex.Vcf.get_record(handle)
+  end
+
+  test "get_records_range returns records by zero-based index" do
+    handle = Noodlex.Vcf.get_handle(@test_vcf_path)
+    all = Noodlex.Vcf.get_all_records(handle)
+
+    assert Noodlex.Vcf.get_records_range(handle, 2, 5) == Enum.slice(all, 2, 3)
+    assert Noodlex.Vcf.get_record(handle) == Enum.at(all, 5)
+    assert Noodlex.Vcf.get_records_range(handle, 2586, 3000) == Enum.slice(all, 2586, 2)
+    assert Noodlex.Vcf.get_records_range(handle, 5, 5) == []
+
+    gzip_handle = Noodlex.Vcf.get_handle(@test_gzip_vcf_path)
+    assert {:error, :not_seekable} = Noodlex.Vcf.get_records_range(gzip_handle, 0, 1)
+  end
+
+  test "CIPOS and CIEND are read as {lower, upper} tuples" do
+    [both, cipos_only, neither] =
+      @confidence_vcf_path |> Noodlex.Vcf.get_handle() |> Noodlex.Vcf.get_all_records()
+
+    assert both.position_confidence_intervals == {-10, 10}
+    assert both.end_confidence_intervals == {-20, 20}
+    assert cipos_only.position_confidence_intervals == {-5, 5}
+    assert cipos_only.end_confidence_intervals == nil
+    assert neither.position_confidence_intervals == nil
+    assert neither.end_confidence_intervals == nil
+  end
+
+  test "on_error: :stop fails the read at the first bad line" do
+    handle = Noodlex.Vcf.get_handle(@interleaved_bad_vcf_path)
+    assert {:error, {:parse, 5, _reason}} = Noodlex.Vcf.get_records(handle, 10, on_error: :stop)
+
+    handle = Noodlex.Vcf.get_handle(@interleaved_bad_vcf_path)
+    assert %{position: 100} = Noodlex.Vcf.get_record(handle, on_error: :stop)
+    assert {:error, {:parse, 5, _reason}} = Noodlex.Vcf.get_record(handle, on_error: :stop)
+  end
+
+  test "on_error: :skip drops bad lines and reads on" do
+    handle = Noodlex.Vcf.get_handle(@interleaved_bad_vcf_path)
+    records = Noodlex.Vcf.get_records(handle, 4, on_error: :skip)
+    assert Enum.map(records, & &1.position) == [100, 300, 500, 700]
+
+    handle = Noodlex.Vcf.get_handle(@interleaved_bad_vcf_path)
+    assert %{position: 100} = Noodlex.Vcf.get_record(handle, on_error: :skip)
+    assert %{position: 300} = Noodlex.Vcf.get_record(handle, on_error: :skip)
+  end
+
+  test "on_error: :collect returns bad lines alongside the records" do
+    handle = Noodlex.Vcf.get_handle(@interleaved_bad_vcf_path)
+    assert {records, errors} = Noodlex.Vcf.get_records(handle, 10, on_error: :collect)
+    assert Enum.map(records, & &1.position) == [100, 300, 500, 700, 800]
+    assert [{5, _}, {7, _}, {9, _}] = errors
+
+    handle = Noodlex.Vcf.get_handle(@interleaved_bad_vcf_path)
+    assert {%{position: 100}, []} = Noodlex.Vcf.get_record(handle, on_error: :collect)
+    assert {%{position: 300}, [{5, _}]} = Noodlex.Vcf.get_record(handle, on_error: :collect)
+    Noodlex.Vcf.skip_records(handle, 10)
+    assert {nil, []} = Noodlex.Vcf.get_record(handle, on_error: :collect)
+  end
+
+  test "symbolic alts declared in ##ALT carry their description" do
+    [declared, undeclared, mixed] =
+      @symbolic_alts_vcf_path |> Noodlex.Vcf.get_handle() |> Noodlex.Vcf.get_all_records()
+
+    deletion = %{id: "DEL", description: "Deletion"}
+    assert declared.resolved_alternate_bases == [deletion]
+    assert undeclared.resolved_alternate_bases == ["<INV>"]
+    assert mixed.resolved_alternate_bases == ["C", deletion]
+    assert mixed.alternate_bases == ["C", "<DEL>"]
+  end
+
+  @tag :tmp_dir
+  test "read_index describes a tabix index without the data file", %{tmp_dir: tmp_dir} do
+    path = Path.join(tmp_dir, "test.vcf.gz")
+    File.cp!(@test_bgzf_vcf_path, path)
+    Noodlex.Vcf.index(path)
+    File.rm!(path)
+
+    index = Noodlex.Vcf.read_index(path)
+
+    assert %{
+             kind: :tbi,
+             format: :vcf,
+             reference_sequence_name_index: 1,
+             start_position_index: 2,
+             end_position_index: nil,
+             line_comment_prefix: "#",
+             line_skip_count: 0
+           } = index
+
+    assert index.contigs == Enum.map(1..22, &"chr#{&1}") ++ ["chrX"]
+    assert %{"chr1" => 135, "chr2" => 220, "chrX" => 17} = index.record_counts
+    assert index.record_counts |> Map.values() |> Enum.sum() == 2588
+
+    assert {:error, :not_found} = Noodlex.Vcf.read_index(Path.join(tmp_dir, "missing.vcf.gz"))
+  end
+
+  test "a binary handle rewinds and reads its records again" do
+    handle = @test_vcf_path |> File.read!() |> Noodlex.Vcf.get_handle_from_binary()
+    assert %{source: :binary, compression: :none} = Noodlex.Vcf.handle_info(handle)
+
+    records = Noodlex.Vcf.get_all_records(handle)
+    assert records == @test_vcf_path |> Noodlex.Vcf.get_handle() |> Noodlex.Vcf.get_all_records()
+
+    assert :ok = Noodlex.Vcf.rewind(handle)
+    assert Noodlex.Vcf.get_all_records(handle) == records
+  end
+
+  @tag :tmp_dir
+  test "max_field_len turns down records with an oversized column", %{tmp_dir: tmp_dir} do
+    path = Path.join(tmp_dir, "oversized.vcf")
+
+    File.write!(path, [
+      "##fileformat=VCFv4.3\n",
+      "##INFO=<ID=BIG,Number=1,Type=String,Description=\"Huge\">\n",
+      "#CHROM\tPOS\tID\tREF\tALT\tQUAL\tFILTER\tINFO\n",
+      "chr1\t100\t.\tA\tC\t50\tPASS\t.\n",
+      "chr1\t200\t.\tA\tC\t50\tPASS\tBIG=#{String.duplicate("x", 100_000)}\n",
+      "chr1\t300\t.\tA\tC\t50\tPASS\t.\n"
+    ])
+
+    assert [_, _, _] = path |> Noodlex.Vcf.get_handle() |> Noodlex.Vcf.get_records(10)
+
+    handle = Noodlex.Vcf.get_handle(path, max_field_len: 1_000)
+    assert %{position: 100} = Noodlex.Vcf.get_record(handle)
+    assert {:error, {:field_too_long, 5, "INFO"}} = Noodlex.Vcf.get_record(handle)
+
+    handle = Noodlex.Vcf.get_handle(path)
+    assert {:error, {:field_too_long, 5, "INFO"}} =
+             Noodlex.Vcf.get_records(handle, 10, max_field_len: 1_000)
+
+    handle = Noodlex.Vcf.get_handle(path, max_field_len: 1_000)
+    assert {[%{position: 100}, %{position: 300}], [{5, _reason}]} =
+             Noodlex.Vcf.get_records_lenient(handle, 10)
+  end
+
+  test "read_raw_records returns the record section verbatim" do
+    handle = Noodlex.Vcf.get_handle(@test_vcf_path)
+    first = Noodlex.Vcf.read_raw_records(handle, 1_000)
+    rest = Noodlex.Vcf.read_raw_records(handle, 10_000)
+    assert length(first) == 1_000
+    assert [] = Noodlex.Vcf.read_raw_records(handle, 10)
+
+    records_section =
+      @test_vcf_path
+      |> File.read!()
+      |> String.split("\n")
+      |> Enum.drop_while(&String.starts_with?(&1, "#"))
+      |> Enum.join("\n")
+
+    assert Enum.join(first ++ rest, "\n") <> "\n" == records_section
+  end
+
+  test "parses records against a header supplied with set_parse_header" do
+    handle = Noodlex.Vcf.get_handle(@undeclared_info_vcf_path)
+    assert %{allele_info: %{}} = Noodlex.Vcf.get_record(handle)
+
+    header = Noodlex.Vcf.get_header(handle)
+    refute Map.has_key?(header.infos, :VAF)
+
+    vaf = %Noodlex.Vcf.Header.Info{
+      id: :VAF,
+      number: :alternate_alleles,
+      type_: :float,
+      description: "Variant allele fraction"
+    }
+
+    assert :ok = Noodlex.Vcf.set_parse_header(handle, put_in(header.infos[:VAF], vaf))
+    assert Noodlex.Vcf.get_header(handle).infos[:VAF] == vaf
+
+    # The reader stays where it was, so the next record is the second one.
+    assert %{position: 200, allele_info: %{"VAF" => %{"C" => 0.5, "G" => 0.125}}} =
+             Noodlex.Vcf.get_record(handle)
+
+    assert :ok = Noodlex.Vcf.reload_header(handle)
+    assert %{allele_info: %{}} = Noodlex.Vcf.get_record(handle)
+  end
+
+  @tag :tmp_dir
+  test "strips carriage returns from CRLF-terminated records", %{tmp_dir: tmp_dir} do
+    path = Path.join(tmp_dir, "crlf.vcf")
+
+    lines = [
+      "##fileformat=VCFv4.3",
+      "##contig=<ID=chr1,length=248956422>",
+      "##FORMAT=<ID=GT,Number=1,Type=String,Description=\"Genotype\">",
+      "##FORMAT=<ID=FT,Number=1,Type=String,Description=\"Sample filter\">",
+      "#CHROM\tPOS\tID\tREF\tALT\tQUAL\tFILTER\tINFO\tFORMAT\ts1",
+      "chr1\t100\t.\tA\tC\t50\tPASS\t.\tGT:FT\t0/1:PASS",
+      "chr1\t200\t.\tT\tG\t50\tPASS\t.\tGT:FT\t1/1:q10",
+      "chr1\t300\t.\tG\tA\t50\tPASS\t.\tGT:FT\t0/0:PASS"
+    ]
+
+    # No line feed after the last carriage return, which noodles leaves in place.
+    File.write!(path, Enum.join(lines, "\r\n") <> "\r")
+
+    handle = Noodlex.Vcf.get_handle(path)
+    assert %{genotypes: %{"s1" => %{"FT" => "PASS"}}} = Noodlex.Vcf.get_record(handle)
+    records = Noodlex.Vcf.get_records(handle, 2)
+
+    assert ["q10", "PASS"] == Enum.map(records, & &1.genotypes["s1"]["FT"])
+    refute inspect(records, limit: :infinity) =~ "\\r"
+  end
+
+  test "counts genotype concordance between two samples" do
+    handle = Noodlex.Vcf.get_handle(@concordance_vcf_path)
+
+    # Phasing and allele order are ignored; the one-sided call at 500 stays out of the ratio.
+    assert %{
+             matching: 3,
+             mismatching: 1,
+             both_missing: 1,
+             one_missing: 1,
+             concordance: 0.75
+           } = Noodlex.Vcf.genotype_concordance(handle, "s1", "s2")
+
+    assert %{position: 100} = Noodlex.Vcf.get_record(handle)
+
+    assert {:error, {:not_found, "s3"}} = Noodlex.Vcf.genotype_concordance(handle, "s1", "s3")
+  end
+
+  @tag :tmp_dir
+  test "sorted? trusts an index and otherwise does not know", %{tmp_dir: tmp_dir} do
+    path = Path.join(tmp_dir, "test.vcf.gz")
+    File.cp!(@test_bgzf_vcf_path, path)
+    assert :unknown == path |> Noodlex.Vcf.get_handle() |> Noodlex.Vcf.sorted?()
+
+    Noodlex.Vcf.index(path)
+    assert true == path |> Noodlex.Vcf.get_handle() |> Noodlex.Vcf.sorted?()
+    assert :unknown == @test_vcf_path |> Noodlex.Vcf.get_handle() |> Noodlex.Vcf.sorted?()
+  end
+
+  @tag :tmp_dir
+  test "reads INFO flags as true", %{tmp_dir: tmp_dir} do
+    handle = Noodlex.Vcf.get_handle(@info_flags_vcf_path)
+    [flagged, unflagged] = Noodlex.Vcf.get_records(handle, 2)
+
+    assert flagged.info == %{"DB" => true, "VALIDATED" => true, "LOWMAP" => true, "DP" => "DP=3"}
+    assert flagged.reserved_info == %{is_in_db_snp: true, is_validated: true, total_depth: 3}
+    assert unflagged.reserved_info == %{total_depth: 7}
+
+    path = Path.join(tmp_dir, "flags.vcf")
+    writer = Noodlex.Vcf.get_writer(path, handle)
+    unset = %{flagged | info: Map.put(flagged.info, "LOWMAP", false)}
+    assert :ok = Noodlex.Vcf.write_records(writer, [flagged, unset])
+    assert :ok = Noodlex.Vcf.close_writer(writer)
+
+    [rewritten, without_lowmap] = path |> Noodlex.Vcf.get_handle() |> Noodlex.Vcf.get_records(2)
+    assert rewritten.info == flagged.info
+    assert without_lowmap.info == Map.delete(flagged.info, "LOWMAP")
+  end
+
+  test "peek_first_record returns the first record and leaves the handle rewound" do
+    handle = Noodlex.Vcf.get_handle(@test_vcf_path)
+    Noodlex.Vcf.get_records(handle, 10)
+
+    peeked = Noodlex.Vcf.peek_first_record(handle)
+    assert [^peeked | _rest] = records = Noodlex.Vcf.get_all_records(handle)
+    assert length(records) == 2588
+
+    empty_handle = Noodlex.Vcf.get_handle(@no_records_vcf_path)
+    assert nil == Noodlex.Vcf.peek_first_record(empty_handle)
+  end
+
+  test "reads MEINFO and METRANS as {name, start, end, polarity} tuples" do
+    [transduced, inserted, malformed, plain] =
+      @mobile_elements_vcf_path |> Noodlex.Vcf.get_handle() |> Noodlex.Vcf.get_all_records()
+
+    assert %{
+             mobile_element_info: {"L1HS", 1, 6019, "+"},
+             mobile_element_transduction_info: {"chr2", 1_000_100, 1_000_400, "-"}
+           } = transduced.reserved_info
+
+    assert inserted.reserved_info.mobile_element_info == {"AluYa5", 2, 281, "-"}
+    refute Map.has_key?(inserted.reserved_info, :mobile_element_transduction_info)
+
+    # Values that do not fit the NAME,START,END,POLARITY form are left as read.
+    assert malformed.reserved_info.mobile_element_info == ["SVA", "start", "1300", "+"]
+    assert plain.reserved_info == %{}
+  end
+
+  test "reads AA as the ancestral allele string" do
+    records =
+      @ancestral_allele_vcf_path |> Noodlex.Vcf.get_handle() |> Noodlex.Vcf.get_all_records()
+
+    assert Enum.map(records, &Map.fetch(&1.reserved_info, :ancestral_allele)) == [
+             {:ok, "G"},
+             {:ok, "t"},
+             {:ok, "-"},
+             {:ok, nil},
+             :error
+           ]
+
+    handle = Noodlex.Vcf.get_handle(@ancestral_allele_vcf_path)
+    [_g, _t, _gap, missing] = Noodlex.Vcf.get_records(handle, 4, missing: :missing)
+    assert missing.reserved_info.ancestral_allele == :missing
+  end
+
+  test "reads DP4 as [ref, alt] forward and reverse strand depths" do
+    [dp4, with_adf, plain] =
+      @strand_depths_vcf_path |> Noodlex.Vcf.get_handle() |> Noodlex.Vcf.get_all_records()
+
+    assert %{
+             strand_read_depths: {10, 12, 3, 4},
+             forward_strand_read_depths: [10, 3],
+             reverse_strand_read_depths: [12, 4]
+           } = dp4.reserved_info
+
+    # A record's own ADF takes precedence over DP4, in the same shape.
+    assert %{forward_strand_read_depths: [7, 2], reverse_strand_read_depths: [12, 4]} =
+             with_adf.reserved_info
+
+    assert plain.reserved_info == %{}
+
+    handle = Noodlex.Vcf.get_handle(@strand_depths_vcf_path)
+    [without_dp4 | _rest] = Noodlex.Vcf.get_records(handle, 3, info: ["ADF"])
+    assert without_dp4.reserved_info == %{}
+  end
+
+  test "reads copy-number INFO as typed values and confidence intervals as tuples" do
+    [segment, bare, plain] =
+      @copy_number_vcf_path |> Noodlex.Vcf.get_handle() |> Noodlex.Vcf.get_all_records()
+
+    assert %{
+             breakend_copy_number: 3,
+             adjacent_copy_number: [2, 4],
+             copy_number_confidence_intervals: {2, 4},
+             adjacent_copy_number_confidence_intervals: [{1, 3}, {3, 5}]
+           } = segment.reserved_info
+
+    assert bare.reserved_info.breakend_copy_number == 1
+    refute Map.has_key?(bare.reserved_info, :adjacent_copy_number)
+    refute Map.has_key?(bare.reserved_info, :copy_number_confidence_intervals)
+    refute Map.has_key?(bare.reserved_info, :adjacent_copy_number_confidence_intervals)
+    assert plain.reserved_info == %{}
+  end
+
+  @tag :tmp_dir
+  test "finishes the JSON output when a line fails before parsing", %{tmp_dir: tmp_dir} do
+    vcf_path = Path.join(tmp_dir, "latin1.vcf")
+
+    File.write!(vcf_path, [
+      "##fileformat=VCFv4.3\n",
+      "#CHROM\tPOS\tID\tREF\tALT\tQUAL\tFILTER\tINFO\n",
+      "chr1\t100\t.\tA\tC\t50\tPASS\t.\n",
+      "chr1\t200\t.\tT\tG\t50\tPASS\tDESC=caf", <<0xE9>>, "\n"
+    ])
+
+    path = Path.join(tmp_dir, "latin1.ndjson.gz")
+    handle = Noodlex.Vcf.get_handle(vcf_path)
+    assert {:error, {:invalid_utf8, 4, _offset}} = Noodlex.Vcf.export_json(handle, path, [])
+
+    # A finished bgzf file ends with the empty EOF block.
+    bgzf_eof =
+      <<0x1F, 0x8B, 8, 4, 0, 0, 0, 0, 0, 0xFF, 6, 0, ?B, ?C, 2, 0, 0x1B, 0, 3, 0, 0, 0, 0, 0, 0,
+        0, 0, 0>>
+
+    assert path |> File.read!() |> String.ends_with?(bgzf_eof)
+  end
+
+  @tag :tmp_dir
+  test "exports records as newline-delimited JSON", %{tmp_dir: tmp_dir} do
+    path = Path.join(tmp_dir, "allele_info.ndjson")
+    handle = Noodlex.Vcf.get_handle(@allele_info_vcf_path)
+    assert 2 = Noodlex.Vcf.export_json(handle, path, [])
+    assert [first, _second] = path |> File.read!() |> String.split("\n", trim: true)
+
+    assert Jason.decode!(first) == %{
+             "chromosome" => "chr1",
+             "position" => 100,
+             "ids" => [],
+             "reference_bases" => "A",
+             "alternate_bases" => ["C", "G"],
+             "quality_score" => 50.0,
+             "filters" => "PASS",
+             "info" => %{"DP" => 22, "AF" => [0.25, 0.5], "AD" => [10, 5, nil]},
+             "format" => [],
+             "genotypes" => %{}
+           }
+
+    path = Path.join(tmp_dir, "typed_genotypes.ndjson")
+    handle = Noodlex.Vcf.get_handle(@typed_genotypes_vcf_path)
+    assert 1 = Noodlex.Vcf.export_json(handle, path, format: ["GT", "GL"])
+    exported = path |> File.read!() |> Jason.decode!()
+    assert %{"format" => ["GT", "GL"], "genotypes" => genotypes} = exported
+
+    assert genotypes == %{
+             "sample1" => %{
+               "GT" => %{"alleles" => [0, 1], "phased" => false},
+               "GL" => [-10.5, -0.5, -20.0]
+             },
+             "sample2" => %{"GT" => %{"alleles" => [nil, nil], "phased" => false}, "GL" => nil}
+           }
+  end
+
+  @tag :tmp_dir
+  test "valid_vcf? checks the header and first record without a handle", %{tmp_dir: tmp_dir} do
+    assert {:ok, :valid} = Noodlex.Vcf.valid_vcf?(@test_vcf_path)
+    assert {:ok, :valid} = Noodlex.Vcf.valid_vcf?(@test_gzip_vcf_path)
+    assert {:ok, :valid} = Noodlex.Vcf.valid_vcf?(@no_records_vcf_path)
+    assert {:error, :not_found} = Noodlex.Vcf.valid_vcf?(Path.join(tmp_dir, "missing.vcf"))
+
+    path = Path.join(tmp_dir, "notes.txt")
+    File.write!(path, "chr1 is the longest chromosome\nchr21 is the shortest\n")
+    assert {:error, {:header, 1, _reason}} = Noodlex.Vcf.valid_vcf?(path)
+  end
+
+  test "reads PS as an integer and lists a sample's phase sets" do
+    handle = Noodlex.Vcf.get_handle(@phased_vcf_path)
+    assert %{genotypes: %{"NA12878" => %{"PS" => 10_100}}} = Noodlex.Vcf.get_record(handle)
+
+    assert [10_100, 10_500, 20_000] = Noodlex.Vcf.phase_sets(handle, "NA12878")
+    assert {:error, {:not_found, "NA12891"}} = Noodlex.Vcf.phase_sets(handle, "NA12891")
+  end
+
+  @tag :tmp_dir
+  test "select_samples writes only the named sample columns", %{tmp_dir: tmp_dir} do
+    handle = Noodlex.Vcf.get_handle(@trio_vcf_path)
+    [record] = Noodlex.Vcf.get_all_records(handle)
+
+    copied_path = Path.join(tmp_dir, "copied.vcf")
+    writer = Noodlex.Vcf.get_writer(copied_path, handle, select_samples: ["mother", "child"])
+    Noodlex.Vcf.rewind(handle)
+    assert 1 = Noodlex.Vcf.copy_records(handle, writer, [])
+    assert :ok = Noodlex.Vcf.close_writer(writer)
+
+    written_path = Path.join(tmp_dir, "written.vcf")
+    writer = Noodlex.Vcf.get_writer(written_path, handle, select_samples: ["mother", "child"])
+    assert :ok = Noodlex.Vcf.write_records(writer, [record])
+    assert :ok = Noodlex.Vcf.close_writer(writer)
+
+    for path <- [copied_path, written_path] do
+      output = Noodlex.Vcf.get_handle(path)
+      # The header's order wins over the order the names were given in.
+      assert Noodlex.Vcf.get_header(output).sample_names == ["child", "mother"]
+      assert String.ends_with?(Noodlex.Vcf.get_raw_record(output), "\tGT\t0/1\t1/1")
+    end
+
+    sites_path = Path.join(tmp_dir, "sites.vcf")
+    writer = Noodlex.Vcf.get_writer(sites_path, handle, select_samples: [])
+    assert :ok = Noodlex.Vcf.write_records(writer, [record])
+    assert :ok = Noodlex.Vcf.close_writer(writer)
+    sites_handle = Noodlex.Vcf.get_handle(sites_path)
+    assert Noodlex.Vcf.get_header(sites_handle).sample_names == []
+    assert [%{format: [], genotypes: %{}}] = Noodlex.Vcf.get_all_records(sites_handle)
+
+    assert {:error, {:not_found, "sibling"}} =
+             Noodlex.Vcf.get_writer(sites_path, handle, select_samples: ["sibling"])
+  end
+
+  test "get_records with a zero count reads nothing" do
+    first = @test_vcf_path |> Noodlex.Vcf.get_handle() |> Noodlex.Vcf.get_record()
+    handle = Noodlex.Vcf.get_handle(@test_vcf_path)
+
+    assert [] = Noodlex.Vcf.get_records(handle, 0)
+    assert [] = Noodlex.Vcf.get_records(handle, 0, on_error: :skip)
+    assert {[], []} = Noodlex.Vcf.get_records(handle, 0, on_error: :collect)
+    assert Noodlex.Vcf.get_record(handle) == first
+  end
+
+  test "types Number=R and Number=G FORMAT values as per-allele and per-genotype lists" do
+    [biallelic, reference_only, triallelic] =
+      @allele_depths_vcf_path |> Noodlex.Vcf.get_handle() |> Noodlex.Vcf.get_all_records()
+
+    assert %{"AD" => [30, 12], "PL" => [120, 0, 450]} = biallelic.genotypes["s1"]
+    # A site without alternate alleles still gets lists, one entry per allele and genotype.
+    assert %{"AD" => [30], "PL" => [0]} = reference_only.genotypes["s1"]
+    # PL follows the VCF genotype order 0/0, 0/1, 1/1, 0/2, 1/2, 2/2.
+    assert %{"AD" => [0, 10, 12], "PL" => [300, 200, 400, 150, 0, 500]} =
+             triallelic.genotypes["s1"]
+  end
+
+  test "get_records_min_qual returns only records meeting the QUAL threshold" do
+    handle = Noodlex.Vcf.get_handle(@mixed_qual_vcf_path)
+    positions = &Enum.map(&1, fn record -> record.position end)
+
+    assert [100, 400] = handle |> Noodlex.Vcf.get_records_min_qual(30, 2) |> positions.()
+    assert [600] = handle |> Noodlex.Vcf.get_records_min_qual(30.0, 10) |> positions.()
+    assert [] = Noodlex.Vcf.get_records_min_qual(handle, 30, 10)
+
+    Noodlex.Vcf.rewind(handle)
+    records = Noodlex.Vcf.get_records_min_qual(handle, 30, 10, keep_missing_qual: true)
+    assert [100, 300, 400, 600] = positions.(records)
+  end
+
+  @tag :tmp_dir
+  test "reads NS as samples_with_data_count, computed only when absent", %{tmp_dir: tmp_dir} do
+    path = Path.join(tmp_dir, "ns.vcf")
+
+    File.write!(path, [
+      "##fileformat=VCFv4.3\n##contig=<ID=chr1,length=248956422>\n",
+      "##INFO=<ID=NS,Number=1,Type=Integer,Description=\"Number of samples with data\">\n",
+      "##FORMAT=<ID=GT,Number=1,Type=String,Description=\"Genotype\">\n",
+      "#CHROM\tPOS\tID\tREF\tALT\tQUAL\tFILTER\tINFO\tFORMAT\ts1\ts2\ts3\n",
+      "chr1\t100\t.\tA\tC\t50\tPASS\tNS=3\tGT\t0/1\t./.\t1/1\n",
+      "chr1\t200\t.\tA\tC\t50\tPASS\t.\tGT\t0/1\t./.\t1/1\n"
+    ])
+
+    handle = Noodlex.Vcf.get_handle(path)
+    [given, absent] = Noodlex.Vcf.get_records(handle, 2, compute_stats: true)
+
+    # The file's NS is kept even though only two of the three samples are called.
+    assert given.reserved_info.samples_with_data_count == 3
+    assert given.info == %{"NS" => "NS=3"}
+    assert absent.reserved_info.samples_with_data_count == 2
+
+    Noodlex.Vcf.rewind(handle)
+    [_given, absent] = Noodlex.Vcf.get_records(handle, 2)
+    refute Map.has_key?(absent.reserved_info, :samples_with_data_count)
+  end
+
+  @tag :tmp_dir
+  test "skips a byte order mark and blank lines before the header", %{tmp_dir: tmp_dir} do
+    path = Path.join(tmp_dir, "bom.vcf")
+
+    lines = [
+      "\uFEFF",
+      "  ",
+      "##fileformat=VCFv4.3",
+      "##contig=<ID=chr1,length=248956422>",
+      "#CHROM\tPOS\tID\tREF\tALT\tQUAL\tFILTER\tINFO",
+      "chr1\t100\t.\tA\tC\t50\tPASS\t.",
+      "chr1\tnot_a_position\t.\tT\tG\t50\tPASS\t."
+    ]
+
+    File.write!(path, Enum.join(lines, "\n") <> "\n")
+
+    assert {:ok, :valid} = Noodlex.Vcf.valid_vcf?(path)
+    handle = Noodlex.Vcf.get_handle(path)
+    assert %{chromosome: "chr1", position: 100} = Noodlex.Vcf.get_record(handle)
+    assert {:error, {:parse, 7, _reason}} = Noodlex.Vcf.get_record(handle)
+
+    Noodlex.Vcf.rewind(handle)
+    assert %{position: 100} = Noodlex.Vcf.get_record(handle)
+  end
+
+  @tag :tmp_dir
+  test "ends the records at a trailing blank line", %{tmp_dir: tmp_dir} do
+    path = Path.join(tmp_dir, "trailing_blank.vcf")
+
+    lines = [
+      "##fileformat=VCFv4.3",
+      "##contig=<ID=chr1,length=248956422>",
+      "##FILTER=<ID=q10,Description=\"Quality below 10\">",
+      "##FORMAT=<ID=GT,Number=1,Type=String,Description=\"Genotype\">",
+      "##FORMAT=<ID=PS,Number=1,Type=Integer,Description=\"Phase set\">",
+      "#CHROM\tPOS\tID\tREF\tALT\tQUAL\tFILTER\tINFO\tFORMAT\ts1\ts2",
+      "chr1\t100\t.\tA\tC\t50\tPASS\t.\tGT:PS\t0|1:100\t0/1:.",
+      "chr1\t200\t.\tT\tG\t5\tq10\t.\tGT:PS\t1|1:100\t1/1:."
+    ]
+
+    File.write!(path, Enum.join(lines, "\n") <> "\n\n")
+    handle = Noodlex.Vcf.get_handle(path)
+
+    assert 2 = Noodlex.Vcf.count_records(handle)
+    assert [{"chr1", 2}] = Noodlex.Vcf.records_per_contig(handle)
+    assert ["chr1"] = Noodlex.Vcf.observed_contigs(handle)
+    assert [%{position: 100}, %{position: 200}] = Noodlex.Vcf.tail(handle, 5)
+    assert %{:pass => 1, "q10" => 1} == Noodlex.Vcf.filter_histogram(handle)
+    assert :ok = Noodlex.Vcf.check_sorted(handle)
+    assert [100] = Noodlex.Vcf.phase_sets(handle, "s1")
+    assert %{matching: 2, concordance: 1.0} = Noodlex.Vcf.genotype_concordance(handle, "s1", "s2")
+    assert [%{position: 100}, %{position: 200}] = Noodlex.Vcf.get_all_records(handle)
+  end
+
+  @tag :tmp_dir
+  test "opens a file with a forced format and compression", %{tmp_dir: tmp_dir} do
+    path = Path.join(tmp_dir, "test.txt")
+    File.cp!(@test_bgzf_vcf_path, path)
+
+    handle = Noodlex.Vcf.get_handle(path, format: :vcf, compression: :bgzf)
+    assert %{compression: :bgzf} = Noodlex.Vcf.handle_info(handle)
+    assert %Noodlex.Vcf.Record{chromosome: "chr1"} = Noodlex.Vcf.get_record(handle)
+
+    # bgzf is valid gzip, so it can be read as a plain gzip stream.
+    gzip_handle = Noodlex.Vcf.get_handle(path, compression: :gzip)
+    assert %{compression: :gzip} = Noodlex.Vcf.handle_info(gzip_handle)
+
+    assert {:error, {:format_mismatch, :none, :bgzf}} =
+             Noodlex.Vcf.get_handle(path, compression: :none)
+
+    assert {:error, {:format_mismatch, :gzip, :none}} =
+             Noodlex.Vcf.get_handle(@test_vcf_path, compression: :gzip)
+
+    assert {:error, {:format_mismatch, :bcf, :vcf}} =
+             Noodlex.Vcf.get_handle(@test_vcf_path, format: :bcf)
+
+    bcf_path = Path.join(tmp_dir, "test.bcf")
+    Noodlex.Vcf.convert(@test_vcf_path, bcf_path)
+
+    assert {:error, {:format_mismatch, :vcf, :bcf}} =
+             Noodlex.Vcf.get_handle(bcf_path, format: :vcf)
+
+    assert {:error, {:unsupported_format, :bcf}} = Noodlex.Vcf.get_handle(bcf_path, format: :bcf)
+
+    assert_raise ArgumentError, fn -> Noodlex.Vcf.get_handle(path, format: :sam) end
+  end
+
+  @tag :tmp_dir
+  test "reports a record that is not valid UTF-8 and reads on", %{tmp_dir: tmp_dir} do
+    path = Path.join(tmp_dir, "latin1.vcf")
+
+    lines = [
+      "##fileformat=VCFv4.3",
+      "##contig=<ID=chr1,length=248956422>",
+      "##INFO=<ID=DESC,Number=1,Type=String,Description=\"Description\">",
+      "#CHROM\tPOS\tID\tREF\tALT\tQUAL\tFILTER\tINFO",
+      "chr1\t100\t.\tA\tC\t50\tPASS\tDESC=plain",
+      # A Latin-1 "é" on its own is not valid UTF-8.
+      "chr1\t200\t.\tT\tG\t50\tPASS\tDESC=caf" <> <<0xE9>>,
+      "chr1\t300\t.\tG\tA\t50\tPASS\tDESC=plain"
+    ]
+
+    File.write!(path, Enum.join(lines, "\n") <> "\n")
+
+    handle = Noodlex.Vcf.get_handle(path)
+    assert %{position: 100} = Noodlex.Vcf.get_record(handle)
+    assert {:error, {:invalid_utf8, 6, 31}} = Noodlex.Vcf.get_record(handle)
+    assert %{line: 7, position: 300} = Noodlex.Vcf.get_record(handle)
+
+    Noodlex.Vcf.rewind(handle)
+    assert {:error, {:invalid_utf8, 6, 31}} = Noodlex.Vcf.get_records(handle, 3)
+    Noodlex.Vcf.rewind(handle)
+    assert 2 = Noodlex.Vcf.skip_records(handle, 2)
+    assert %{position: 300} = Noodlex.Vcf.get_record(handle)
+
+    Noodlex.Vcf.rewind(handle)
+
+    assert [%{position: 100}, %{position: 300}] =
+             Noodlex.Vcf.get_records(handle, 3, on_error: :skip)
+
+    Noodlex.Vcf.rewind(handle)
+
+    assert {[%{position: 100}, %{position: 300}], [{6, "invalid UTF-8 at byte 31"}]} =
+             Noodlex.Vcf.get_records(handle, 3, on_error: :collect)
+
+    Noodlex.Vcf.rewind(handle)
+
+    assert {[_, _], [{6, "invalid UTF-8 at byte 31"}]} =
+             Noodlex.Vcf.get_records_lenient(handle, 2)
+  end
+
+  test "reads a whole file in batches through a cursor" do
+    handle = Noodlex.Vcf.get_handle(@test_vcf_path)
+
+    batches =
+      Stream.unfold(handle, fn
+        nil -> nil
+        cursor -> Noodlex.Vcf.get_records_with_cursor(cursor, 500)
+      end)
+      |> Enum.to_list()
+
+    assert Enum.map(batches, &length/1) == [500, 500, 500, 500, 500, 88]
+
+    expected = @test_vcf_path |> Noodlex.Vcf.get_handle() |> Noodlex.Vcf.get_all_records()
+    assert Enum.concat(batches) == expected
+
+    chr2 = batches |> Stream.flat_map(& &1) |> Enum.filter(&(&1.chromosome == "chr2"))
+    assert length(chr2) == Enum.count(expected, &(&1.chromosome == "chr2"))
+
+    assert_raise ArgumentError, fn -> Noodlex.Vcf.get_records_with_cursor(handle, 0) end
+  end
+
   defp get_batch(_handle, batch, 1_000) do
     Enum.reverse(batch)
   end
@@ -60,6 +1908,33 @@ defmodule Noodlex.VcfTest do
     end
   end
 
+  # Waits until `pid` is inside the named NIF, so that it holds whatever locks that call takes.
+  defp await_nif_call(pid, function) do
+    case Process.info(pid, :current_function) do
+      {:current_function, {Noodlex.Vcf, ^function, _arity}} ->
+        :ok
+
+      _ ->
+        Process.sleep(1)
+        await_nif_call(pid, function)
+    end
+  end
+
+  # Compresses `data` into one bgzf block and the EOF block, for input `convert` refuses to read.
+  defp bgzip(data) do
+    z = :zlib.open()
+    :ok = :zlib.deflateInit(z, :default, :deflated, -15, 8, :default)
+    compressed = z |> :zlib.deflate(data, :finish) |> IO.iodata_to_binary()
+    :zlib.close(z)
+
+    block_size = byte_size(compressed) + 26
+
+    <<0x1F, 0x8B, 8, 4, 0::32, 0, 0xFF, 6::little-16, ?B, ?C, 2::little-16,
+      block_size - 1::little-16, compressed::binary, :erlang.crc32(data)::little-32,
+      byte_size(data)::little-32, 0x1F, 0x8B, 8, 4, 0::32, 0, 0xFF, 6::little-16, ?B, ?C,
+      2::little-16, 0x1B::little-16, 3, 0, 0::32, 0::32>>
+  end
+
   defp read_all_records(handle), do: read_all_records(handle, [])
 
   defp read_all_records(handle, results) do